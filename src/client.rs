@@ -0,0 +1,384 @@
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use openssl::ssl::{HandshakeError, SslConnector, SslMethod, SslStream};
+
+use crate::http::{Error, Limits, Method, Request, Response, StatusCode};
+use crate::url::URL;
+
+/// A blocking HTTP/1.1 client which follows redirects and reuses keep-alive connections.
+pub struct Client {
+    tls: SslConnector,
+    idle: Mutex<HashMap<Origin, Vec<BufReader<Transport>>>>,
+    max_idle_per_host: usize,
+    max_redirects: usize,
+    timeout: Option<Duration>,
+    limits: Limits,
+}
+
+impl Client {
+    pub fn new() -> Self {
+        Self {
+            tls: SslConnector::builder(SslMethod::tls())
+                .expect("Failed to initialise OpenSSL!")
+                .build(),
+            idle: Mutex::new(HashMap::new()),
+            max_idle_per_host: 4,
+            max_redirects: 10,
+            timeout: Some(Duration::from_secs(30)),
+            limits: Limits::new(),
+        }
+    }
+
+    /// How many redirects are followed before giving up with `Error::TooManyRedirects`.
+    pub fn with_max_redirects(self, max_redirects: usize) -> Self {
+        Self { max_redirects, ..self }
+    }
+
+    /// How many idle keep-alive connections are kept open to each host.
+    pub fn with_max_idle_per_host(self, max_idle_per_host: usize) -> Self {
+        Self { max_idle_per_host, ..self }
+    }
+
+    /// The time allowed for connecting and for each read or write on a connection.
+    pub fn with_timeout(self, timeout: Option<Duration>) -> Self {
+        Self { timeout, ..self }
+    }
+
+    pub fn with_limits(self, limits: Limits) -> Self {
+        Self { limits, ..self }
+    }
+
+    pub fn get(&self, url: &str) -> Result<Response, Error> {
+        let url = URL::from_string(url).map_err(|_| Error::URLParse)?;
+        self.send(Request::new(Method::GET, url))
+    }
+
+    /// Sends a request, following any redirects, and returns the final response.
+    pub fn send(&self, request: Request) -> Result<Response, Error> {
+        let mut request = request;
+
+        for _ in 0..=self.max_redirects {
+            let response = self.send_once(&request)?;
+
+            let location = match response.header().get_first("Location") {
+                Some(location) if is_followed(response.code()) => resolve(request.url(), location)?,
+                _ => return Ok(response),
+            };
+
+            request = redirect(request, response.code(), location);
+        }
+
+        Err(Error::TooManyRedirects)
+    }
+
+    fn send_once(&self, request: &Request) -> Result<Response, Error> {
+        let origin = Origin::from(request.url())?;
+        let request = request.clone()
+            .without_header("Host")
+            .with_header("Host", origin.host_header());
+
+        loop {
+            let (mut con, reused) = match self.checkout(&origin) {
+                Some(con) => (con, true),
+                None => (self.connect(&origin)?, false),
+            };
+
+            let sent = request.write_to(con.get_mut())
+                .and_then(|_| con.get_mut().flush().map_err(Error::IOError))
+                .and_then(|_| await_response(&mut con));
+
+            match sent {
+                Ok(()) => {}
+
+                // A pooled connection may have been closed by the server while idle, in which case nothing of a
+                // response arrives. The request may still have been received, so only an idempotent one is resent.
+                Err(Error::ConnectionClosed | Error::IOError(_)) if reused && request.method().is_idempotent() => continue,
+
+                Err(e) => return Err(e),
+            }
+
+            let response = self.read_response(&mut con, request.method())?;
+            if response.keep_alive() {
+                self.checkin(origin, con);
+            }
+            return Ok(response);
+        }
+    }
+
+    fn read_response(&self, con: &mut BufReader<Transport>, method: Method) -> Result<Response, Error> {
+        loop {
+            let response = Response::read_for(con, method, &self.limits)?;
+            if !response.code().is_informational() || response.code() == StatusCode::SWITCHING_PROTOCOLS {
+                return Ok(response);
+            }
+        }
+    }
+
+    fn checkout(&self, origin: &Origin) -> Option<BufReader<Transport>> {
+        self.idle.lock().unwrap().get_mut(origin)?.pop()
+    }
+
+    fn checkin(&self, origin: Origin, con: BufReader<Transport>) {
+        let mut idle = self.idle.lock().unwrap();
+        let connections = idle.entry(origin).or_default();
+        if connections.len() < self.max_idle_per_host {
+            connections.push(con);
+        }
+    }
+
+    fn connect(&self, origin: &Origin) -> Result<BufReader<Transport>, Error> {
+        let addrs = (origin.host.as_str(), origin.port).to_socket_addrs().map_err(Error::IOError)?;
+
+        let mut last_error = None;
+        let mut tcp = None;
+        for addr in addrs {
+            let attempt = match self.timeout {
+                Some(timeout) => TcpStream::connect_timeout(&addr, timeout),
+                None => TcpStream::connect(addr),
+            };
+
+            match attempt {
+                Ok(t) => {
+                    tcp = Some(t);
+                    break;
+                }
+                Err(e) => last_error = Some(e),
+            }
+        }
+
+        let tcp = match (tcp, last_error) {
+            (Some(tcp), _) => tcp,
+            (None, Some(e)) => return Err(Error::IOError(e)),
+            (None, None) => return Err(Error::URLParse),
+        };
+
+        tcp.set_read_timeout(self.timeout).map_err(Error::IOError)?;
+        tcp.set_write_timeout(self.timeout).map_err(Error::IOError)?;
+
+        let transport = if origin.secure {
+            let tls = self.tls.connect(&origin.host, tcp).map_err(|e| match e {
+                HandshakeError::SetupFailure(e) => Error::TLSError(e.into()),
+                HandshakeError::Failure(s) | HandshakeError::WouldBlock(s) => Error::TLSError(s.into_error()),
+            })?;
+            Transport::Secure(Box::new(tls))
+        } else {
+            Transport::Plain(tcp)
+        };
+
+        Ok(BufReader::new(transport))
+    }
+}
+
+impl Default for Client {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Origin {
+    secure: bool,
+    host: String,
+    port: u16,
+}
+
+impl Origin {
+    fn from(url: &URL) -> Result<Self, Error> {
+        let secure = match url.protocol().map(|p| p.to_ascii_lowercase()).as_deref() {
+            None | Some("http") => false,
+            Some("https") => true,
+            Some(_) => return Err(Error::URLParse),
+        };
+
+        Ok(Self {
+            secure,
+            host: url.host().ok_or(Error::URLParse)?.clone(),
+            port: url.port().copied().unwrap_or(if secure { 443 } else { 80 }),
+        })
+    }
+
+    fn host_header(&self) -> String {
+        match (self.secure, self.port) {
+            (false, 80) | (true, 443) => self.host.clone(),
+            (_, port) => format!("{}:{}", self.host, port),
+        }
+    }
+}
+
+enum Transport {
+    Plain(TcpStream),
+    Secure(Box<SslStream<TcpStream>>),
+}
+
+impl Read for Transport {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Transport::Plain(s) => s.read(buf),
+            Transport::Secure(s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for Transport {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Transport::Plain(s) => s.write(buf),
+            Transport::Secure(s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Transport::Plain(s) => s.flush(),
+            Transport::Secure(s) => s.flush(),
+        }
+    }
+}
+
+fn await_response(con: &mut BufReader<Transport>) -> Result<(), Error> {
+    match con.fill_buf() {
+        Ok([]) => Err(Error::ConnectionClosed),
+        Ok(_) => Ok(()),
+        // A server which is merely slow to answer has not dropped the connection.
+        Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => Err(Error::Timeout),
+        Err(e) => Err(Error::IOError(e)),
+    }
+}
+
+fn resolve(base: &URL, location: &str) -> Result<URL, Error> {
+    let location = location.trim();
+
+    if location.contains("://") {
+        return URL::from_string(location).map_err(|_| Error::URLParse);
+    }
+
+    if let Some(rest) = location.strip_prefix("//") {
+        let protocol = base.protocol().map(|p| p.as_str()).unwrap_or("http");
+        return URL::from_string(&format!("{}://{}", protocol, rest)).map_err(|_| Error::URLParse);
+    }
+
+    let path = if location.starts_with('/') {
+        location.to_string()
+    } else {
+        let mut segments = base.resource().clone();
+        segments.pop();
+        format!("/{}", segments.iter().map(|s| format!("{}/", crate::url::encode(s))).collect::<String>() + location)
+    };
+
+    let url = URL::from_string(&path).map_err(|_| Error::URLParse)?;
+    Ok(url
+        .with_protocol(base.protocol().map(String::as_str))
+        .with_host(base.host().map(String::as_str))
+        .with_port(base.port().copied()))
+}
+
+fn is_followed(code: StatusCode) -> bool {
+    matches!(code.as_u16(), 301 | 302 | 303 | 307 | 308)
+}
+
+fn redirect(request: Request, code: StatusCode, location: URL) -> Request {
+    let same_origin = Origin::from(request.url()).ok() == Origin::from(&location).ok();
+
+    // 303, and for historical reasons 301 and 302 answering a POST, turn the request into a GET without a body.
+    let as_get = code == StatusCode::SEE_OTHER
+        || ((code == StatusCode::MOVED_PERMANENTLY || code == StatusCode::FOUND) && request.method() == Method::POST);
+    if !as_get && same_origin {
+        return request.with_url(location);
+    }
+
+    let method = if as_get && request.method() != Method::HEAD { Method::GET } else { request.method() };
+    let mut redirected = Request::new(method, location);
+
+    for (key, value) in request.header() {
+        let is = |names: &[&str]| names.iter().any(|n| n.eq_ignore_ascii_case(key));
+        let dropped_for_body = as_get && is(&["Content-Length", "Content-Type", "Transfer-Encoding"]);
+        let dropped_for_origin = !same_origin && is(&["Authorization", "Cookie"]);
+        if !dropped_for_body && !dropped_for_origin {
+            redirected = redirected.with_header(key, value);
+        }
+    }
+
+    if !as_get {
+        let content_type = request.header().content_type().unwrap_or("application/octet-stream").to_string();
+        redirected = redirected.with_body(&content_type, request.body().clone());
+    }
+
+    redirected
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::http::{Bindings, EndpointResponder, WebServer};
+    use crate::server::Server;
+    use std::thread::{sleep, spawn};
+
+    struct Hop;
+
+    impl EndpointResponder for Hop {
+        fn response(&self, _: Request, bindings: Bindings) -> Response {
+            match bindings.get("n").unwrap().parse::<usize>().unwrap() {
+                0 => Response::from_text(StatusCode::OK, "text/plain", "landed"),
+                n => Response::redirect(&format!("/hop/{}", n - 1)),
+            }
+        }
+    }
+
+    #[test]
+    fn follows_redirects() {
+        let mut server = Server::new(WebServer::new().with_endpoint(Method::GET, "/hop/<n>", Hop))
+            .with_port(8082)
+            .with_grace_period(Duration::from_secs(1));
+        let shutdown = server.shutdown_handle();
+        let server = spawn(move || server.run());
+        sleep(Duration::from_millis(200));
+
+        let client = Client::new().with_max_redirects(3);
+        let response = client.get("http://127.0.0.1:8082/hop/3").unwrap();
+        assert_eq!(response.code(), StatusCode::OK);
+        assert!(matches!(client.get("http://127.0.0.1:8082/hop/4"), Err(Error::TooManyRedirects)));
+
+        drop(client);
+        shutdown.shutdown();
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn retries_idempotent_requests() {
+        // A server which answers a single request on each connection, but claims to keep it alive.
+        let listener = std::net::TcpListener::bind("127.0.0.1:8084").unwrap();
+        let server = spawn(move || {
+            for _ in 0..2 {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    line.clear();
+                }
+                reader.get_mut().write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok").unwrap();
+            }
+        });
+
+        let client = Client::new();
+        let url = || URL::from_string("http://127.0.0.1:8084/").unwrap();
+        assert_eq!(client.send(Request::new(Method::GET, url())).unwrap().code(), StatusCode::OK);
+        sleep(Duration::from_millis(100));
+        assert_eq!(client.send(Request::new(Method::GET, url())).unwrap().code(), StatusCode::OK);
+        sleep(Duration::from_millis(100));
+        assert!(client.send(Request::new(Method::POST, url())).is_err());
+
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn resolves_locations() {
+        let base = URL::from_string("https://example.com:8443/a/b?c=d").unwrap();
+        assert_eq!(resolve(&base, "/x").unwrap().as_string().unwrap(), "https://example.com:8443/x");
+        assert_eq!(resolve(&base, "y").unwrap().as_string().unwrap(), "https://example.com:8443/a/y");
+        assert_eq!(resolve(&base, "//other.org/z").unwrap().as_string().unwrap(), "https://other.org/z");
+    }
+}
@@ -0,0 +1,62 @@
+use std::fmt::{Debug, Formatter};
+use std::io::{Read, Write};
+
+use super::chunked::write_chunked;
+use super::Error;
+
+pub enum Body {
+    Bytes(Vec<u8>),
+    Sized(Box<dyn Read + Send>, u64),
+    Chunked(Box<dyn Read + Send>),
+    /// A body of unknown length sent as it is, delimited by closing the connection afterwards.
+    Unsized(Box<dyn Read + Send>),
+}
+
+impl Body {
+    pub fn empty() -> Self {
+        Body::Bytes(Vec::new())
+    }
+
+    pub fn len(&self) -> Option<u64> {
+        match self {
+            Body::Bytes(b) => Some(b.len() as u64),
+            Body::Sized(_, len) => Some(*len),
+            Body::Chunked(_) | Body::Unsized(_) => None,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == Some(0)
+    }
+
+    /// Copies the body onto `writer`, returning its length without any framing.
+    pub fn write_to<W: Write>(self, writer: &mut W) -> Result<u64, Error> {
+        match self {
+            Body::Bytes(bytes) => writer.write_all(&bytes).map(|_| bytes.len() as u64).map_err(Error::IOError),
+
+            Body::Sized(reader, len) => {
+                let copied = std::io::copy(&mut reader.take(len), writer).map_err(Error::IOError)?;
+                if copied < len {
+                    return Err(Error::IOError(std::io::ErrorKind::UnexpectedEof.into()));
+                }
+
+                Ok(copied)
+            }
+
+            Body::Chunked(mut reader) => write_chunked(&mut reader, writer),
+
+            Body::Unsized(mut reader) => std::io::copy(&mut reader, writer).map_err(Error::IOError),
+        }
+    }
+}
+
+impl Debug for Body {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Body::Bytes(b) => f.debug_tuple("Bytes").field(b).finish(),
+            Body::Sized(_, len) => f.debug_tuple("Sized").field(len).finish(),
+            Body::Chunked(_) => f.debug_tuple("Chunked").finish(),
+            Body::Unsized(_) => f.debug_tuple("Unsized").finish(),
+        }
+    }
+}
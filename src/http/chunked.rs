@@ -0,0 +1,107 @@
+use std::io::{BufRead, Read, Write};
+
+use super::{read_header, read_line, Error, Header, Limits};
+
+const MAX_LINE: usize = 8 * 1024;
+
+/// Decodes a chunked message body from `reader`, appending any trailer fields to `header`.
+pub fn read_chunked<R: BufRead>(reader: &mut R, header: &mut Header, limits: &Limits) -> Result<Vec<u8>, Error> {
+    let mut body = Vec::new();
+    let mut line = String::new();
+
+    loop {
+        line.clear();
+        read_line(reader, &mut line, MAX_LINE, Error::RequestParse)?;
+
+        let size = line.trim_end().split(';').next().unwrap_or("").trim();
+        let size = usize::from_str_radix(size, 16).map_err(|_| Error::RequestParse)?;
+
+        if size == 0 {
+            break;
+        }
+
+        if size > limits.max_body_size() - body.len() {
+            return Err(Error::PayloadTooLarge);
+        }
+
+        let start = body.len();
+        body.resize(start + size, 0);
+        reader
+            .read_exact(&mut body[start..])
+            .map_err(Error::IOError)?;
+
+        line.clear();
+        read_line(reader, &mut line, MAX_LINE, Error::RequestParse)?;
+        if line != "\r\n" {
+            return Err(Error::RequestParse);
+        }
+    }
+
+    for (key, value) in read_header(reader, limits)? {
+        header.add(key, value);
+    }
+
+    Ok(body)
+}
+
+/// Writes everything readable from `body` to `writer` using the chunked transfer coding.
+pub fn write_chunked<R: Read + ?Sized, W: Write>(body: &mut R, writer: &mut W) -> Result<u64, Error> {
+    let mut buffer = [0u8; 8192];
+    let mut written = 0;
+
+    loop {
+        let n = match body.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(Error::IOError(e)),
+        };
+
+        write!(writer, "{:X}\r\n", n).map_err(Error::IOError)?;
+        writer.write_all(&buffer[..n]).map_err(Error::IOError)?;
+        writer.write_all(b"\r\n").map_err(Error::IOError)?;
+        written += n as u64;
+    }
+
+    writer.write_all(b"0\r\n\r\n").map_err(Error::IOError)?;
+    Ok(written)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn decode_with_trailers() {
+        let mut input = Cursor::new(&b"4\r\nWiki\r\n6;ext=1\r\npedia \r\nE\r\nin \r\n\r\nchunks.\r\n0\r\nExpires: never\r\n\r\nGET"[..]);
+        let mut header = Header::new();
+
+        let body = read_chunked(&mut input, &mut header, &Limits::new()).unwrap();
+
+        assert_eq!(body, b"Wikipedia in \r\n\r\nchunks.");
+        assert_eq!(header.get_first("Expires"), Some("never"));
+
+        let mut rest = String::new();
+        input.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "GET");
+    }
+
+    #[test]
+    fn encode_round_trip() {
+        let mut encoded = Vec::new();
+        write_chunked(&mut &b"Funny Monkey!"[..], &mut encoded).unwrap();
+
+        let mut header = Header::new();
+        let decoded = read_chunked(&mut Cursor::new(encoded), &mut header, &Limits::new()).unwrap();
+        assert_eq!(decoded, b"Funny Monkey!");
+    }
+
+    #[test]
+    fn body_limit() {
+        let mut input = Cursor::new(&b"8\r\n12345678\r\n8\r\n12345678\r\n0\r\n\r\n"[..]);
+        let limits = Limits::new().with_max_body_size(10);
+
+        assert!(matches!(read_chunked(&mut input, &mut Header::new(), &limits), Err(Error::PayloadTooLarge)));
+    }
+}
@@ -0,0 +1,263 @@
+use std::io::Write;
+
+use flate2::read::{DeflateEncoder, GzEncoder};
+use flate2::write;
+
+use super::conditional::split_tags;
+use super::{Body, Header, Middleware, Request, Response, StatusCode};
+use crate::mime::is_compressible;
+
+/// Middleware which compresses response bodies with gzip or deflate when the client accepts it through
+/// `Accept-Encoding`. Only bodies of a compressible media type and at least `threshold` bytes long are compressed,
+/// and streamed bodies are compressed as they are sent.
+pub struct Compression {
+    threshold: u64,
+    level: flate2::Compression,
+}
+
+impl Compression {
+    pub fn new() -> Self {
+        Self {
+            threshold: 1024,
+            level: flate2::Compression::default(),
+        }
+    }
+
+    /// The smallest body worth compressing, below which the overhead of compression outweighs the saving.
+    pub fn with_threshold(self, threshold: u64) -> Self {
+        Self { threshold, ..self }
+    }
+
+    /// The compression level, from 0 for none to 9 for the smallest output.
+    pub fn with_level(self, level: u32) -> Self {
+        Self { level: flate2::Compression::new(level.min(9)), ..self }
+    }
+
+    fn compress(&self, coding: &str, body: Body) -> Body {
+        match body {
+            Body::Bytes(bytes) => {
+                let compressed = match coding {
+                    "gzip" => {
+                        let mut encoder = write::GzEncoder::new(Vec::new(), self.level);
+                        encoder.write_all(&bytes).and_then(|_| encoder.finish())
+                    }
+                    _ => {
+                        let mut encoder = write::DeflateEncoder::new(Vec::new(), self.level);
+                        encoder.write_all(&bytes).and_then(|_| encoder.finish())
+                    }
+                };
+                Body::Bytes(compressed.expect("Writing to a Vec cannot fail"))
+            }
+
+            Body::Sized(reader, _) | Body::Chunked(reader) => Body::Chunked(self.encoder(coding, reader)),
+            Body::Unsized(reader) => Body::Unsized(self.encoder(coding, reader)),
+        }
+    }
+
+    fn encoder(&self, coding: &str, reader: Box<dyn std::io::Read + Send>) -> Box<dyn std::io::Read + Send> {
+        match coding {
+            "gzip" => Box::new(GzEncoder::new(reader, self.level)),
+            _ => Box::new(DeflateEncoder::new(reader, self.level)),
+        }
+    }
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Middleware for Compression {
+    // Lets validators the client got from a compressed response match those of the resource itself, so that
+    // conditional requests for it are evaluated as they would be without compression.
+    fn before(&self, req: &mut Request) -> Option<Response> {
+        for name in ["If-None-Match", "If-Match"] {
+            let list = match req.header().get_first(name) {
+                Some(list) => list.to_string(),
+                None => continue,
+            };
+
+            let uncoded: Vec<_> = split_tags(&list).into_iter().filter_map(uncoded_etag).collect();
+            if !uncoded.is_empty() {
+                req.header_mut().replace(name, format!("{}, {}", list, uncoded.join(", ")));
+            }
+        }
+        None
+    }
+
+    fn after(&self, req: &Request, mut res: Response) -> Response {
+        // A client revalidating a compressed response must be told the validator of that compressed response.
+        if res.code() == StatusCode::NOT_MODIFIED {
+            let etag = res.header().get_first("ETag").map(String::from);
+            let tags = req.header().get_first("If-None-Match").map(split_tags).unwrap_or_default();
+            let coded = CODINGS.iter()
+                .filter_map(|coding| coded_etag(etag.as_deref()?, coding))
+                .find(|coded| tags.contains(&coded.as_str()));
+
+            return match coded {
+                Some(coded) if res.header().contains_token("Vary", "Accept-Encoding") => {
+                    res.without_header("ETag").with_header("ETag", coded)
+                }
+                Some(coded) => res.without_header("ETag").with_header("ETag", coded).with_header("Vary", "Accept-Encoding"),
+                None => res,
+            };
+        }
+
+        let compressible = res.header().content_type().map(is_compressible).unwrap_or(false);
+        if !compressible || res.header().get_first("Content-Encoding").is_some() {
+            return res;
+        }
+
+        // Whether or not this response is compressed, another request for it may be, so caches need to know.
+        if !res.header().contains_token("Vary", "Accept-Encoding") {
+            res = res.with_header("Vary", "Accept-Encoding");
+        }
+
+        // A response to HEAD is chosen and described just as one to GET would be, and its body dropped later.
+        let code = res.code();
+        if !code.is_success()
+            || code == StatusCode::NO_CONTENT
+            || code == StatusCode::PARTIAL_CONTENT
+            || res.header().contains_token("Cache-Control", "no-transform")
+            || res.body().len().map(|len| len < self.threshold).unwrap_or(false)
+        {
+            return res;
+        }
+
+        let coding = match negotiate(req.header(), CODINGS) {
+            Some(coding) => coding,
+            None => return res,
+        };
+
+        let body = res.replace_body(Body::empty());
+        let body = self.compress(coding, body);
+        let res = match &body {
+            Body::Bytes(bytes) => res.without_header("Content-Length").with_header("Content-Length", bytes.len().to_string()),
+            Body::Chunked(_) if !res.header().contains_token("Transfer-Encoding", "chunked") => res
+                .without_header("Content-Length")
+                .with_header("Transfer-Encoding", "chunked"),
+            _ => res.without_header("Content-Length"),
+        };
+        let mut res = res.with_header("Content-Encoding", coding);
+        res.replace_body(body);
+
+        // The compressed body is a different representation, so a strong validator must change with it.
+        match res.header().get_first("ETag").and_then(|etag| coded_etag(etag, coding)) {
+            Some(etag) => res.without_header("ETag").with_header("ETag", etag),
+            None => res,
+        }
+    }
+}
+
+const CODINGS: &[&str] = &["gzip", "deflate"];
+
+fn coded_etag(etag: &str, coding: &str) -> Option<String> {
+    let opaque = etag.strip_prefix('"')?.strip_suffix('"')?;
+    Some(format!("\"{}-{}\"", opaque, coding))
+}
+
+fn uncoded_etag(tag: &str) -> Option<String> {
+    CODINGS.iter()
+        .find_map(|coding| tag.strip_suffix(&format!("-{}\"", coding)))
+        .map(|opaque| format!("{}\"", opaque))
+}
+
+pub(crate) fn negotiate(header: &Header, supported: &[&'static str]) -> Option<&'static str> {
+    let accepted = header.quality_list("Accept-Encoding");
+    let wildcard = accepted.iter().find(|(coding, _)| *coding == "*").map(|(_, q)| *q);
+
+    let mut best: Option<(&'static str, f32)> = None;
+    for &coding in supported {
+        let quality = accepted.iter()
+            .find(|(c, _)| c.eq_ignore_ascii_case(coding))
+            .map(|(_, q)| *q)
+            .or(wildcard)
+            .unwrap_or(0.0);
+
+        if quality > 0.0 && best.map(|(_, q)| quality > q).unwrap_or(true) {
+            best = Some((coding, quality));
+        }
+    }
+
+    best.map(|(coding, _)| coding)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::http::conditional::{evaluate, Precondition};
+    use crate::http::{middleware, Method};
+    use crate::url::URL;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    fn accepting(encodings: &str) -> Header {
+        let mut header = Header::new();
+        header.add("Accept-Encoding", encodings);
+        header
+    }
+
+    #[test]
+    fn negotiation() {
+        let supported = &["gzip", "deflate"];
+        assert_eq!(negotiate(&Header::new(), supported), None);
+        assert_eq!(negotiate(&accepting("gzip, deflate, br"), supported), Some("gzip"));
+        assert_eq!(negotiate(&accepting("gzip;q=0.5, deflate"), supported), Some("deflate"));
+        assert_eq!(negotiate(&accepting("*;q=0.1, gzip;q=0"), supported), Some("deflate"));
+        assert_eq!(negotiate(&accepting("br, identity"), supported), None);
+    }
+
+    #[test]
+    fn round_trip() {
+        let text = "compress me ".repeat(200);
+        let req = Request::new(Method::GET, URL::from_string("/").unwrap())
+            .with_header("Accept-Encoding", "gzip");
+        let respond = || Response::from_text(StatusCode::OK, "text/plain", &text).with_header("ETag", "\"abc\"");
+
+        let res = Compression::new().after(&req, respond());
+        assert_eq!(res.header().get_first("Content-Encoding"), Some("gzip"));
+        assert_eq!(res.header().get_first("ETag"), Some("\"abc-gzip\""));
+        assert_eq!(res.header().get_first("Vary"), Some("Accept-Encoding"));
+
+        let compressed = match res.body() {
+            Body::Bytes(bytes) => bytes,
+            _ => unreachable!(),
+        };
+        assert_eq!(res.header().content_length(), Some(compressed.len() as u64));
+
+        let mut decompressed = String::new();
+        GzDecoder::new(&compressed[..]).read_to_string(&mut decompressed).unwrap();
+        assert_eq!(decompressed, text);
+
+        let res = Compression::new().with_threshold(1 << 20).after(&req, respond());
+        assert!(res.header().get_first("Content-Encoding").is_none());
+    }
+
+    #[test]
+    fn revalidation() {
+        let text = "compress me ".repeat(200);
+        let layers: Vec<Box<dyn Middleware + Send + Sync>> = vec![Box::new(Compression::new())];
+        let respond = |req: Request| {
+            let req = req.with_header("Accept-Encoding", "gzip");
+            middleware::apply(&layers, req, |req| match evaluate(&req, "\"abc\"", None) {
+                Precondition::NotModified => Response::new(StatusCode::NOT_MODIFIED).with_header("ETag", "\"abc\""),
+                _ => Response::from_text(StatusCode::OK, "text/plain", &text).with_header("ETag", "\"abc\""),
+            })
+        };
+        let get = |method| Request::new(method, URL::from_string("/").unwrap());
+
+        let res = respond(get(Method::GET));
+        let etag = res.header().get_first("ETag").unwrap().to_string();
+        assert_eq!(etag, "\"abc-gzip\"");
+
+        let res = respond(get(Method::GET).with_header("If-None-Match", etag.as_str()));
+        assert_eq!(res.code(), StatusCode::NOT_MODIFIED);
+        assert_eq!(res.header().get_first("ETag"), Some(etag.as_str()));
+        assert_eq!(res.header().get_first("Vary"), Some("Accept-Encoding"));
+
+        let res = respond(get(Method::HEAD));
+        assert_eq!(res.header().get_first("Content-Encoding"), Some("gzip"));
+        assert_eq!(res.header().get_first("ETag"), Some(etag.as_str()));
+    }
+}
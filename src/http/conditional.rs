@@ -0,0 +1,119 @@
+use super::date::parse_http_date;
+use super::{DateTime, Method, Request};
+
+/// The outcome of evaluating a request's conditional headers against the current state of a resource.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Precondition {
+    Proceed,
+    NotModified,
+    Failed,
+}
+
+/// Computes a strong entity tag for a file from its size and modification time.
+pub fn file_etag(len: u64, modified: Option<DateTime>) -> String {
+    format!("\"{:x}-{:x}\"", len, modified.map(|d| d.timestamp()).unwrap_or(0))
+}
+
+/// Evaluates `If-Match`, `If-Unmodified-Since`, `If-None-Match` and `If-Modified-Since` in the order given by
+/// RFC 7232 section 6.
+pub fn evaluate(req: &Request, etag: &str, last_modified: Option<DateTime>) -> Precondition {
+    let header = req.header();
+
+    if let Some(tags) = header.get_first("If-Match") {
+        if !matches_any(tags, etag, true) {
+            return Precondition::Failed;
+        }
+    } else if let Some(date) = header.get_first("If-Unmodified-Since").and_then(parse_http_date) {
+        if last_modified.map(|m| m > date).unwrap_or(true) {
+            return Precondition::Failed;
+        }
+    }
+
+    let safe = matches!(req.method(), Method::GET | Method::HEAD);
+
+    if let Some(tags) = header.get_first("If-None-Match") {
+        if matches_any(tags, etag, false) {
+            return if safe { Precondition::NotModified } else { Precondition::Failed };
+        }
+    } else if let Some(date) = header.get_first("If-Modified-Since").and_then(parse_http_date) {
+        if safe && last_modified.map(|m| m <= date).unwrap_or(false) {
+            return Precondition::NotModified;
+        }
+    }
+
+    Precondition::Proceed
+}
+
+/// Whether the validator in an `If-Range` header still identifies the current representation.
+pub fn if_range_matches(value: &str, etag: &str, last_modified: Option<DateTime>) -> bool {
+    let value = value.trim();
+
+    if value.starts_with('"') || value.starts_with("W/") {
+        strong_eq(value, etag)
+    } else {
+        matches!((parse_http_date(value), last_modified), (Some(a), Some(b)) if a == b)
+    }
+}
+
+fn matches_any(list: &str, etag: &str, strong: bool) -> bool {
+    if list.trim() == "*" {
+        return true;
+    }
+
+    split_tags(list).into_iter().any(|tag| {
+        if strong {
+            strong_eq(tag, etag)
+        } else {
+            opaque(tag) == opaque(etag)
+        }
+    })
+}
+
+fn strong_eq(a: &str, b: &str) -> bool {
+    !a.starts_with("W/") && !b.starts_with("W/") && a == b
+}
+
+fn opaque(tag: &str) -> &str {
+    tag.strip_prefix("W/").unwrap_or(tag)
+}
+
+pub(crate) fn split_tags(list: &str) -> Vec<&str> {
+    let mut tags = Vec::new();
+    let mut quoted = false;
+    let mut start = 0;
+
+    for (i, c) in list.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                tags.push(list[start..i].trim());
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+
+    tags.push(list[start..].trim());
+    tags.retain(|t| !t.is_empty());
+    tags
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn entity_tag_lists() {
+        assert!(matches_any("\"a\", W/\"b\"", "\"b\"", false));
+        assert!(!matches_any("\"a\", W/\"b\"", "\"b\"", true));
+        assert!(matches_any("\"x,y\", \"z\"", "\"x,y\"", true));
+        assert!(matches_any("*", "\"anything\"", true));
+    }
+
+    #[test]
+    fn if_range() {
+        assert!(if_range_matches("\"1-2\"", "\"1-2\"", None));
+        assert!(!if_range_matches("W/\"1-2\"", "\"1-2\"", None));
+        assert!(!if_range_matches("Sun, 06 Nov 1994 08:49:37 GMT", "\"1-2\"", None));
+    }
+}
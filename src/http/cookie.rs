@@ -1,30 +1,161 @@
+use std::collections::HashMap;
 use std::fmt::{Display, Formatter};
 
+use chrono::{NaiveDateTime, TimeZone, Utc};
+
+use super::date::{format_http_date, parse_http_date};
+use super::header::is_valid_name;
+use super::{Error, Header};
+
 pub type DateTime = chrono::DateTime<chrono::Utc>;
 
+/// Which cross-site requests a browser attaches a cookie to.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    /// Sent with all requests, which browsers only accept for `Secure` cookies.
+    None,
+}
+
+impl SameSite {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Strict => "Strict",
+            Self::Lax => "Lax",
+            Self::None => "None",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Cookie {
     name: String,
     value: String,
     expiration: Option<DateTime>,
+    max_age: Option<i64>,
+    path: Option<String>,
+    domain: Option<String>,
+    same_site: Option<SameSite>,
     http_only: bool,
     secure: bool,
+    partitioned: bool,
 }
 
 impl Cookie {
+    /// Creates a cookie. One whose name is not a token or whose value contains characters RFC 6265 does not allow is
+    /// dropped with a warning when added to a response, use `try_new` to handle it instead.
     pub fn new(name: &str, value: &str) -> Self {
         Self {
             name: name.to_string(),
             value: value.to_string(),
             expiration: None,
+            max_age: None,
+            path: None,
+            domain: None,
+            same_site: None,
             http_only: false,
             secure: false,
+            partitioned: false,
+        }
+    }
+
+    /// Creates a cookie, failing with `Error::InvalidCookie` if the name or value is invalid. A value wrapped in
+    /// double quotes is accepted and kept as it is.
+    pub fn try_new(name: &str, value: &str) -> Result<Self, Error> {
+        let cookie = Self::new(name, value);
+        if cookie.is_valid() {
+            Ok(cookie)
+        } else {
+            Err(Error::InvalidCookie)
+        }
+    }
+
+    pub(crate) fn is_valid(&self) -> bool {
+        is_valid_name(&self.name)
+            && is_valid_value(&self.value)
+            && self.path.as_deref().map(is_valid_attribute).unwrap_or(true)
+            && self.domain.as_deref().map(is_valid_attribute).unwrap_or(true)
+    }
+
+    /// Parses the value of a `Set-Cookie` header. Unknown attributes and attributes with malformed values are
+    /// ignored, as RFC 6265 requires of user agents.
+    pub fn parse(s: &str) -> Result<Self, Error> {
+        let mut parts = s.split(';');
+        let (name, value) = parts.next()
+            .and_then(|pair| pair.split_once('='))
+            .ok_or(Error::InvalidCookie)?;
+        let mut cookie = Self::try_new(name.trim(), value.trim())?;
+
+        for attribute in parts {
+            let (key, value) = match attribute.split_once('=') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => (attribute.trim(), ""),
+            };
+
+            match key.to_ascii_lowercase().as_str() {
+                "expires" => cookie.expiration = parse_expires(value).or(cookie.expiration),
+                "max-age" => cookie.max_age = value.parse().ok().or(cookie.max_age),
+                "path" if value.starts_with('/') => cookie.path = Some(value.to_string()),
+                "domain" if !value.is_empty() => cookie.domain = Some(value.trim_start_matches('.').to_ascii_lowercase()),
+                "samesite" => {
+                    cookie.same_site = match value.to_ascii_lowercase().as_str() {
+                        "strict" => Some(SameSite::Strict),
+                        "lax" => Some(SameSite::Lax),
+                        "none" => Some(SameSite::None),
+                        _ => cookie.same_site,
+                    }
+                }
+                "httponly" => cookie.http_only = true,
+                "secure" => cookie.secure = true,
+                "partitioned" => cookie.partitioned = true,
+                _ => (),
+            }
         }
+
+        Ok(cookie)
     }
 
     pub fn with_expiration(self, expiration: DateTime) -> Self {
         Self { expiration: Some(expiration), .. self }
     }
 
+    /// Sets how many seconds the cookie lives for, which takes precedence over `Expires`. Zero or less expires it
+    /// immediately.
+    pub fn with_max_age(self, seconds: i64) -> Self {
+        Self { max_age: Some(seconds), .. self }
+    }
+
+    pub fn with_path(self, path: &str) -> Self {
+        Self { path: Some(path.to_string()), .. self }
+    }
+
+    /// Sets the path, failing with `Error::InvalidCookie` if it contains `;` or control characters.
+    pub fn try_with_path(self, path: &str) -> Result<Self, Error> {
+        if is_valid_attribute(path) {
+            Ok(self.with_path(path))
+        } else {
+            Err(Error::InvalidCookie)
+        }
+    }
+
+    pub fn with_domain(self, domain: &str) -> Self {
+        Self { domain: Some(domain.to_string()), .. self }
+    }
+
+    /// Sets the domain, failing with `Error::InvalidCookie` if it contains `;` or control characters.
+    pub fn try_with_domain(self, domain: &str) -> Result<Self, Error> {
+        if is_valid_attribute(domain) {
+            Ok(self.with_domain(domain))
+        } else {
+            Err(Error::InvalidCookie)
+        }
+    }
+
+    pub fn with_same_site(self, same_site: SameSite) -> Self {
+        Self { same_site: Some(same_site), .. self }
+    }
+
     pub fn with_http_only(self, http_only: bool) -> Self {
         Self { http_only, .. self }
     }
@@ -32,6 +163,70 @@ impl Cookie {
     pub fn with_secure(self, secure: bool) -> Self {
         Self { secure, .. self }
     }
+
+    /// Asks browsers to keep the cookie in storage partitioned by the top-level site (CHIPS).
+    pub fn with_partitioned(self, partitioned: bool) -> Self {
+        Self { partitioned, .. self }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    pub fn unquoted_value(&self) -> &str {
+        unquote(&self.value)
+    }
+
+    pub fn expiration(&self) -> Option<DateTime> {
+        self.expiration
+    }
+
+    pub fn max_age(&self) -> Option<i64> {
+        self.max_age
+    }
+
+    pub fn path(&self) -> Option<&str> {
+        self.path.as_deref()
+    }
+
+    pub fn domain(&self) -> Option<&str> {
+        self.domain.as_deref()
+    }
+
+    pub fn same_site(&self) -> Option<SameSite> {
+        self.same_site
+    }
+
+    pub fn http_only(&self) -> bool {
+        self.http_only
+    }
+
+    pub fn secure(&self) -> bool {
+        self.secure
+    }
+
+    pub fn partitioned(&self) -> bool {
+        self.partitioned
+    }
+
+    /// Whether this cookie tells the browser to delete any cookie it has with the same name, path and domain.
+    pub fn is_removal(&self) -> bool {
+        self.max_age.map(|a| a <= 0).unwrap_or(false)
+            || self.expiration.map(|e| e <= Utc::now()).unwrap_or(false)
+    }
+
+    fn into_removal(self) -> Self {
+        Self {
+            value: String::new(),
+            expiration: Some(Utc.timestamp_opt(0, 0).unwrap()),
+            max_age: Some(0),
+            .. self
+        }
+    }
 }
 
 impl Display for Cookie {
@@ -39,7 +234,23 @@ impl Display for Cookie {
         write!(f, "{}={}", self.name, self.value)?;
 
         if let Some(expire) = &self.expiration {
-            write!(f, "; Expires={}", expire.to_rfc2822())?;
+            write!(f, "; Expires={}", format_http_date(expire))?;
+        }
+
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={}", max_age)?;
+        }
+
+        if let Some(path) = &self.path {
+            write!(f, "; Path={}", path)?;
+        }
+
+        if let Some(domain) = &self.domain {
+            write!(f, "; Domain={}", domain)?;
+        }
+
+        if let Some(same_site) = self.same_site {
+            write!(f, "; SameSite={}", same_site.as_str())?;
         }
 
         if self.http_only {
@@ -50,6 +261,158 @@ impl Display for Cookie {
             write!(f, "; Secure")?;
         }
 
+        if self.partitioned {
+            write!(f, "; Partitioned")?;
+        }
+
         Ok(())
     }
 }
+
+/// Parses the value of a request's `Cookie` header into name and value pairs, skipping malformed pairs and
+/// removing the double quotes around quoted values.
+pub fn parse_cookie_header(s: &str) -> Vec<(&str, &str)> {
+    s.split(';')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(name, value)| (name.trim(), value.trim()))
+        .filter(|(name, value)| is_valid_name(name) && is_valid_value(value))
+        .map(|(name, value)| (name, unquote(value)))
+        .collect()
+}
+
+/// The cookies a request was sent with, and the changes to them to send back with the response.
+#[derive(Debug, Clone, Default)]
+pub struct CookieJar {
+    original: HashMap<String, String>,
+    delta: Vec<Cookie>,
+}
+
+impl CookieJar {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_header(header: &Header) -> Self {
+        Self {
+            original: header.cookies().into_iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            delta: Vec::new(),
+        }
+    }
+
+    /// The current value of a cookie, taking changes made to the jar into account.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        match self.delta.iter().rev().find(|c| c.name == name) {
+            Some(cookie) if cookie.is_removal() => None,
+            Some(cookie) => Some(cookie.unquoted_value()),
+            None => self.original.get(name).map(|v| v.as_str()),
+        }
+    }
+
+    /// Sets a cookie, replacing any earlier change to the cookie with the same name, path and domain.
+    pub fn add(&mut self, cookie: Cookie) {
+        self.delta.retain(|c| !(c.name == cookie.name && c.path == cookie.path && c.domain == cookie.domain));
+        self.delta.push(cookie);
+    }
+
+    /// Deletes a cookie from the browser. The path and domain of `cookie` must match those it was set with.
+    pub fn remove(&mut self, cookie: Cookie) {
+        self.add(cookie.into_removal());
+    }
+
+    /// The cookies to send as `Set-Cookie` headers for the changes made to the jar.
+    pub fn delta(&self) -> impl Iterator<Item = &Cookie> {
+        self.delta.iter()
+    }
+}
+
+fn parse_expires(value: &str) -> Option<DateTime> {
+    parse_http_date(value).or_else(|| {
+        NaiveDateTime::parse_from_str(value, "%a, %d-%b-%Y %H:%M:%S GMT")
+            .ok()
+            .map(|d| Utc.from_utc_datetime(&d))
+    })
+}
+
+fn unquote(value: &str) -> &str {
+    value.strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .unwrap_or(value)
+}
+
+fn is_valid_value(value: &str) -> bool {
+    let value = if value.len() >= 2 { unquote(value) } else { value };
+    value.bytes().all(|b| matches!(b, 0x21 | 0x23..=0x2B | 0x2D..=0x3A | 0x3C..=0x5B | 0x5D..=0x7E))
+}
+
+fn is_valid_attribute(value: &str) -> bool {
+    !value.bytes().any(|b| b == b';' || b.is_ascii_control())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::http::{Response, StatusCode};
+
+    #[test]
+    fn set_cookie_round_trip() {
+        let cookie = Cookie::new("id", "a3fWa")
+            .with_max_age(3600)
+            .with_path("/docs")
+            .with_domain("example.com")
+            .with_same_site(SameSite::Lax)
+            .with_http_only(true)
+            .with_secure(true)
+            .with_partitioned(true);
+
+        let text = cookie.to_string();
+        assert_eq!(text, "id=a3fWa; Max-Age=3600; Path=/docs; Domain=example.com; SameSite=Lax; HttpOnly; Secure; Partitioned");
+        assert_eq!(Cookie::parse(&text).unwrap(), cookie);
+
+        let parsed = Cookie::parse("lang=\"en\"; expires=Wed, 21-Oct-2015 07:28:00 GMT; SameSite=bogus; Path=relative").unwrap();
+        assert_eq!(parsed.unquoted_value(), "en");
+        assert!(parsed.expiration().is_some());
+        assert_eq!(parsed.same_site(), None);
+        assert_eq!(parsed.path(), None);
+    }
+
+    #[test]
+    fn validation() {
+        assert!(Cookie::try_new("a b", "x").is_err());
+        assert!(Cookie::try_new("a", "x;y").is_err());
+        assert!(Cookie::try_new("a", "x y").is_err());
+        assert!(Cookie::try_new("a", "\"quoted\"").is_ok());
+        assert!(Cookie::parse("no-equals-sign").is_err());
+
+        let response = Response::new(StatusCode::OK)
+            .with_cookie(Cookie::new("a", "x; Domain=evil.example"))
+            .with_cookie(Cookie::new("a b", "x"))
+            .with_cookie(Cookie::new("a", "x").with_path("/; Domain=evil.example"))
+            .with_cookie(Cookie::new("a", "x").with_domain("example.com\r\nX-Evil: 1"))
+            .with_cookie(Cookie::new("a", "x").with_path("/docs"));
+        assert_eq!(response.header().get_all("Set-Cookie"), vec!["a=x; Path=/docs"]);
+
+        assert!(Cookie::new("a", "x").try_with_path("/; Secure").is_err());
+        assert!(Cookie::new("a", "x").try_with_domain("evil.example;").is_err());
+        assert!(Cookie::new("a", "x").try_with_domain("example.com").is_ok());
+    }
+
+    #[test]
+    fn cookie_header() {
+        assert_eq!(parse_cookie_header("a=1; b=\"2\";bad; c=x y; d="), vec![("a", "1"), ("b", "2"), ("d", "")]);
+    }
+
+    #[test]
+    fn jar_deltas() {
+        let mut header = Header::new();
+        header.add("Cookie", "session=abc; theme=dark");
+        let mut jar = CookieJar::from_header(&header);
+
+        jar.add(Cookie::new("theme", "light"));
+        jar.remove(Cookie::new("session", ""));
+
+        assert_eq!(jar.get("theme"), Some("light"));
+        assert_eq!(jar.get("session"), None);
+        assert_eq!(jar.delta().count(), 2);
+        assert!(jar.delta().any(|c| c.name() == "session" && c.to_string().contains("Max-Age=0")));
+    }
+}
@@ -0,0 +1,218 @@
+use openssl::base64::{decode_block, encode_block};
+use openssl::hash::MessageDigest;
+use openssl::memcmp;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use openssl::symm::{decrypt_aead, encrypt_aead, Cipher};
+
+use super::Cookie;
+use crate::url::{decode, encode};
+
+const KEY_LEN: usize = 64;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
+/// A secret used to sign and encrypt cookies. The first half keys HMAC-SHA256, the second AES-256-GCM.
+#[derive(Clone)]
+pub struct CookieKey {
+    bytes: [u8; KEY_LEN],
+}
+
+impl CookieKey {
+    /// Generates a new random key. Keys must be kept and reused, or every cookie made with them becomes invalid.
+    pub fn generate() -> Self {
+        let mut bytes = [0; KEY_LEN];
+        openssl::rand::rand_bytes(&mut bytes).expect("Failed to generate cookie key!");
+        Self { bytes }
+    }
+
+    /// Creates a key from 64 bytes of secret material, such as one loaded from configuration.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != KEY_LEN {
+            return None;
+        }
+
+        let mut key = Self { bytes: [0; KEY_LEN] };
+        key.bytes.copy_from_slice(bytes);
+        Some(key)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    fn signing(&self) -> &[u8] {
+        &self.bytes[..32]
+    }
+
+    fn encryption(&self) -> &[u8] {
+        &self.bytes[32..]
+    }
+
+    fn mac(&self, name: &str, value: &str) -> Vec<u8> {
+        let key = PKey::hmac(self.signing()).expect("Failed to load cookie key!");
+        let mut signer = Signer::new(MessageDigest::sha256(), &key).expect("Failed to initialise HMAC!");
+        // The name is signed along with the value so a signed value cannot be replayed under another cookie.
+        signer.update(name.as_bytes()).and_then(|_| signer.update(b"="))
+            .and_then(|_| signer.update(value.as_bytes()))
+            .expect("Failed to compute HMAC!");
+        signer.sign_to_vec().expect("Failed to compute HMAC!")
+    }
+}
+
+impl std::fmt::Debug for CookieKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("CookieKey(..)")
+    }
+}
+
+/// The key new cookies are signed and encrypted with, along with previous keys which are still accepted so that
+/// keys can be rotated without logging everyone out.
+#[derive(Debug, Clone)]
+pub struct CookieKeys {
+    current: CookieKey,
+    previous: Vec<CookieKey>,
+}
+
+impl CookieKeys {
+    pub fn new(current: CookieKey) -> Self {
+        Self { current, previous: Vec::new() }
+    }
+
+    pub fn with_previous(mut self, key: CookieKey) -> Self {
+        self.previous.push(key);
+        self
+    }
+
+    fn all(&self) -> impl Iterator<Item = &CookieKey> {
+        std::iter::once(&self.current).chain(self.previous.iter())
+    }
+
+    /// Prefixes `value` with a MAC so tampering can be detected. The value stays readable, but is percent-encoded
+    /// so that it may contain any characters and still be a valid cookie value.
+    pub fn sign(&self, name: &str, value: &str) -> String {
+        let value = encode(&value);
+        format!("{}.{}", encode_block(&self.current.mac(name, &value)), value)
+    }
+
+    pub fn verify(&self, name: &str, signed: &str) -> Option<String> {
+        let (mac, value) = signed.split_once('.')?;
+        let mac = decode_block(mac).ok()?;
+
+        let valid = self.all().any(|key| {
+            let expected = key.mac(name, value);
+            expected.len() == mac.len() && memcmp::eq(&expected, &mac)
+        });
+
+        if valid {
+            decode(value).ok()
+        } else {
+            None
+        }
+    }
+
+    pub fn encrypt(&self, name: &str, value: &str) -> String {
+        let mut nonce = [0; NONCE_LEN];
+        openssl::rand::rand_bytes(&mut nonce).expect("Failed to generate nonce!");
+
+        let mut tag = [0; TAG_LEN];
+        let ciphertext = encrypt_aead(
+            Cipher::aes_256_gcm(),
+            self.current.encryption(),
+            Some(&nonce),
+            name.as_bytes(),
+            value.as_bytes(),
+            &mut tag,
+        ).expect("Failed to encrypt cookie!");
+
+        let mut sealed = nonce.to_vec();
+        sealed.extend(ciphertext);
+        sealed.extend(tag);
+        encode_block(&sealed)
+    }
+
+    pub fn decrypt(&self, name: &str, sealed: &str) -> Option<String> {
+        let sealed = decode_block(sealed).ok()?;
+        if sealed.len() < NONCE_LEN + TAG_LEN {
+            return None;
+        }
+
+        let (nonce, rest) = sealed.split_at(NONCE_LEN);
+        let (ciphertext, tag) = rest.split_at(rest.len() - TAG_LEN);
+
+        self.all()
+            .find_map(|key| {
+                decrypt_aead(Cipher::aes_256_gcm(), key.encryption(), Some(nonce), name.as_bytes(), ciphertext, tag).ok()
+            })
+            .and_then(|plain| String::from_utf8(plain).ok())
+    }
+}
+
+impl Cookie {
+    pub fn new_signed(name: &str, value: &str, keys: &CookieKeys) -> Self {
+        Self::new(name, &keys.sign(name, value))
+    }
+
+    pub fn new_private(name: &str, value: &str, keys: &CookieKeys) -> Self {
+        Self::new(name, &keys.encrypt(name, value))
+    }
+
+    pub fn verify(&self, keys: &CookieKeys) -> Option<String> {
+        keys.verify(self.name(), self.unquoted_value())
+    }
+
+    pub fn decrypt(&self, keys: &CookieKeys) -> Option<String> {
+        keys.decrypt(self.name(), self.unquoted_value())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn signed() {
+        let keys = CookieKeys::new(CookieKey::generate());
+        let cookie = Cookie::new_signed("user", "alice", &keys);
+
+        assert_eq!(cookie.verify(&keys).as_deref(), Some("alice"));
+        assert_eq!(keys.verify("user", &cookie.value().replace("alice", "admin")), None);
+        assert_eq!(keys.verify("other", cookie.value()), None);
+        assert_eq!(CookieKeys::new(CookieKey::generate()).verify("user", cookie.value()), None);
+    }
+
+    #[test]
+    fn signed_arbitrary_values() {
+        let keys = CookieKeys::new(CookieKey::generate());
+        for value in &["alice smith", "{\"a\":1,\"b\":\"x;y\"}", "é.\\ ,;\"", ""] {
+            let cookie = Cookie::new_signed("prefs", value, &keys);
+            let parsed = Cookie::parse(&cookie.to_string()).unwrap();
+            assert_eq!(parsed.verify(&keys).as_deref(), Some(*value));
+        }
+    }
+
+    #[test]
+    fn private() {
+        let keys = CookieKeys::new(CookieKey::generate());
+        let cookie = Cookie::new_private("cart", "3 apples; 2 pears", &keys);
+
+        assert_eq!(cookie.decrypt(&keys).as_deref(), Some("3 apples; 2 pears"));
+        assert!(!cookie.value().contains("apples"));
+        assert_eq!(keys.decrypt("other", cookie.value()), None);
+
+        let mut tampered = decode_block(cookie.value()).unwrap();
+        tampered[NONCE_LEN] ^= 1;
+        assert_eq!(keys.decrypt("cart", &encode_block(&tampered)), None);
+    }
+
+    #[test]
+    fn rotation() {
+        let old = CookieKey::generate();
+        let signed = CookieKeys::new(old.clone()).sign("id", "42");
+        let sealed = CookieKeys::new(old.clone()).encrypt("id", "42");
+
+        let rotated = CookieKeys::new(CookieKey::generate()).with_previous(old);
+        assert_eq!(rotated.verify("id", &signed).as_deref(), Some("42"));
+        assert_eq!(rotated.decrypt("id", &sealed).as_deref(), Some("42"));
+    }
+}
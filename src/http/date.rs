@@ -0,0 +1,38 @@
+use chrono::{NaiveDateTime, TimeZone, Utc};
+use std::time::SystemTime;
+
+use super::DateTime;
+
+/// Formats a timestamp as an IMF-fixdate, the preferred format for dates in HTTP headers.
+pub fn format_http_date(date: &DateTime) -> String {
+    date.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+/// Parses an IMF-fixdate, falling back to the obsolete RFC 850 and asctime formats.
+pub fn parse_http_date(s: &str) -> Option<DateTime> {
+    let s = s.trim();
+
+    ["%a, %d %b %Y %H:%M:%S GMT", "%A, %d-%b-%y %H:%M:%S GMT", "%a %b %e %H:%M:%S %Y"]
+        .iter()
+        .find_map(|f| NaiveDateTime::parse_from_str(s, f).ok())
+        .map(|d| Utc.from_utc_datetime(&d))
+}
+
+/// Converts a file timestamp to a `DateTime`, truncated to the whole seconds HTTP dates can express.
+pub fn from_system_time(time: SystemTime) -> DateTime {
+    let date: DateTime = time.into();
+    Utc.timestamp_opt(date.timestamp(), 0).unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let date = parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT").unwrap();
+        assert_eq!(format_http_date(&date), "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"), Some(date));
+        assert_eq!(parse_http_date("Sun Nov  6 08:49:37 1994"), Some(date));
+    }
+}
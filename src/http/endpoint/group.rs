@@ -0,0 +1,139 @@
+use std::borrow::Borrow;
+use std::sync::Arc;
+
+use super::*;
+use crate::http::middleware::{self, Middleware};
+
+type Handler = Box<dyn EndpointResponder + Send + Sync>;
+
+/// Endpoints sharing a prefix and middleware, added to a `WebServer` with `WebServer::with_group`. The prefix may
+/// contain variables like any other resource, whose bindings are passed to every endpoint in the group.
+///
+/// The group's middleware only runs around requests to its endpoints, inside any middleware of the server.
+pub struct EndpointGroup {
+    prefix: String,
+    endpoints: Vec<(Endpoint, Handler)>,
+    middleware: Vec<Box<dyn Middleware + Send + Sync>>,
+}
+
+impl EndpointGroup {
+    pub fn new<S: Borrow<str> + ?Sized>(prefix: &S) -> Self {
+        Self {
+            prefix: prefix.borrow().to_string(),
+            endpoints: Vec::new(),
+            middleware: Vec::new(),
+        }
+    }
+
+    /// Adds an endpoint whose resource is relative to the group's prefix.
+    pub fn with_endpoint<S, H>(mut self, method: Method, endpoint: &S, handler: H) -> Self
+        where S: Borrow<str> + ?Sized, H: EndpointResponder + Send + Sync + 'static
+    {
+        let endpoint = Endpoint::new(method, endpoint.borrow()).with_prefix(&self.prefix);
+        self.endpoints.push((endpoint, Box::new(handler)));
+        self
+    }
+
+    pub fn with_named_endpoint<S, H>(mut self, name: &str, method: Method, endpoint: &S, handler: H) -> Self
+        where S: Borrow<str> + ?Sized, H: EndpointResponder + Send + Sync + 'static
+    {
+        let endpoint = Endpoint::new(method, endpoint.borrow()).with_prefix(&self.prefix).with_name(name);
+        self.endpoints.push((endpoint, Box::new(handler)));
+        self
+    }
+
+    /// Adds middleware to run around requests to this group's endpoints, after any added before it.
+    pub fn with_middleware(mut self, middleware: impl Middleware + Send + Sync + 'static) -> Self {
+        self.middleware.push(Box::new(middleware));
+        self
+    }
+
+    /// Nests another group under this one's prefix. Its endpoints run inside this group's middleware as well as
+    /// their own.
+    pub fn with_group(mut self, group: EndpointGroup) -> Self {
+        for (endpoint, handler) in group.into_endpoints() {
+            self.endpoints.push((endpoint.with_prefix(&self.prefix), handler));
+        }
+        self
+    }
+
+    pub(crate) fn into_endpoints(self) -> Vec<(Endpoint, Handler)> {
+        if self.middleware.is_empty() {
+            return self.endpoints;
+        }
+
+        let middleware = Arc::new(self.middleware);
+        self.endpoints.into_iter()
+            .map(|(endpoint, handler)| {
+                let layered = Layered { middleware: middleware.clone(), handler };
+                (endpoint, Box::new(layered) as Handler)
+            })
+            .collect()
+    }
+}
+
+struct Layered {
+    middleware: Arc<Vec<Box<dyn Middleware + Send + Sync>>>,
+    handler: Handler,
+}
+
+impl EndpointResponder for Layered {
+    fn response(&self, request: Request, bindings: Bindings) -> Response {
+        middleware::apply(&self.middleware, request, |request| self.handler.response(request, bindings))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::http::StatusCode;
+    use crate::url::URL;
+
+    struct Echo;
+
+    impl EndpointResponder for Echo {
+        fn response(&self, request: Request, bindings: Bindings) -> Response {
+            let mut bindings: Vec<_> = bindings.into_iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+            bindings.sort();
+            Response::new(StatusCode::OK)
+                .with_header("X-Bindings", bindings.join(","))
+                .with_header("X-Seen", request.header().get_all("X-Layer").join(","))
+        }
+    }
+
+    struct Layer(&'static str);
+
+    impl Middleware for Layer {
+        fn before(&self, req: &mut Request) -> Option<Response> {
+            *req = req.clone().with_header("X-Layer", self.0);
+            None
+        }
+    }
+
+    #[test]
+    fn nesting() {
+        let group = EndpointGroup::new("/api/<version:u32>")
+            .with_middleware(Layer("outer"))
+            .with_endpoint(Method::GET, "/status", Echo)
+            .with_group(EndpointGroup::new("/users").with_middleware(Layer("inner")).with_endpoint(Method::GET, "/<id>", Echo));
+
+        let mut table = EndpointTable::new();
+        for (endpoint, handler) in group.into_endpoints() {
+            table.add(endpoint, handler).unwrap();
+        }
+
+        let respond = |path: &str| {
+            let url = URL::from_string(path).unwrap();
+            let (handler, bindings) = table.find_match(Method::GET, &url).unwrap();
+            handler.response(Request::new(Method::GET, url), bindings)
+        };
+
+        let response = respond("/api/1/status");
+        assert_eq!(response.header().get_first("X-Bindings"), Some("version=1"));
+        assert_eq!(response.header().get_first("X-Seen"), Some("outer"));
+
+        let response = respond("/api/2/users/7");
+        assert_eq!(response.header().get_first("X-Bindings"), Some("id=7,version=2"));
+        assert_eq!(response.header().get_first("X-Seen"), Some("outer,inner"));
+    }
+}
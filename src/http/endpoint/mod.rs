@@ -1,14 +1,18 @@
+mod group;
 mod parse;
 
-use crate::http::{Method, Request, Response};
+use crate::http::{IntoResponse, Method, Request, Response};
 use std::fmt::{Display, Formatter};
 
+pub use group::EndpointGroup;
 pub use parse::Bindings;
 pub use parse::EndpointTable;
+pub use parse::NamedEndpoints;
 
 pub struct Endpoint {
     method: Method,
     resource: String,
+    name: Option<String>,
 }
 
 impl Endpoint {
@@ -16,9 +20,23 @@ impl Endpoint {
         Endpoint {
             method,
             resource: resource.to_string(),
+            name: None,
         }
     }
 
+    /// Names the endpoint so that URLs to it can be built with `NamedEndpoints::url_for`.
+    pub fn with_name(self, name: &str) -> Self {
+        Self { name: Some(name.to_string()), ..self }
+    }
+
+    pub fn with_prefix(self, prefix: &str) -> Self {
+        Self { resource: format!("{}/{}", prefix, self.resource), ..self }
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
     pub fn verb(&self) -> Method {
         self.method
     }
@@ -30,4 +48,14 @@ impl Endpoint {
 
 pub trait EndpointResponder {
     fn response(&self, request: Request, bindings: Bindings) -> Response;
-}
\ No newline at end of file
+}
+
+/// Closures taking the request and its bindings can be used as endpoints. For closures taking typed inputs
+/// instead, see `extract::extract`.
+impl<F, R> EndpointResponder for F
+    where F: Fn(Request, Bindings) -> R, R: IntoResponse
+{
+    fn response(&self, request: Request, bindings: Bindings) -> Response {
+        self(request, bindings).into_response()
+    }
+}
@@ -1,100 +1,331 @@
-use std::collections::{HashMap};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use regex::Regex;
 
 use super::*;
+use crate::http::Error;
 use crate::url::URL;
 
+type Handler = Box<dyn EndpointResponder + Send + Sync>;
+
+/// The endpoints of a server, arranged as a tree of path segments so that a request is matched by walking down it.
+///
+/// A segment of an endpoint's resource is one of
+/// * a constant, such as `users`, which must match exactly,
+/// * a variable, `<name>`, which matches any single segment,
+/// * a typed variable, `<name:u64>`, which only matches segments parsing as one of `u8`, `u16`, `u32`, `u64`,
+///   `usize`, `i8`, `i16`, `i32`, `i64`, `isize`, `f32`, `f64` or `bool`,
+/// * a constrained variable, `<name:regex>`, which only matches segments matching the whole of `regex`, which
+///   cannot contain `/`,
+/// * a tail, `<name..>`, which must be last and matches the rest of the path, including nothing at all.
+///
+/// Where several endpoints match a path, constants take precedence over typed and constrained variables, which
+/// in turn take precedence over plain variables and then tails, segment by segment from the start of the path.
 pub struct EndpointTable {
-    endpoints: Vec<(EndpointURI, Box<dyn EndpointResponder + Send + Sync>)>,
+    root: Node,
+    named: Arc<NamedEndpoints>,
 }
 
 impl EndpointTable {
     pub fn new() -> Self {
-        Self { endpoints: Vec::new() }
+        Self { root: Node::default(), named: Arc::default() }
+    }
+
+    /// Adds an endpoint, failing with `Error::InvalidEndpoint` if its resource cannot be parsed, or with
+    /// `Error::DuplicateEndpoint` if an endpoint with the same method already matches exactly the same paths or
+    /// its name is already given to another resource.
+    pub fn add(&mut self, endpoint: Endpoint, handler: Handler) -> Result<(), Error> {
+        let segments = to_segments(endpoint.resource())?;
+        if let Some(name) = endpoint.name() {
+            self.named.check(name, &segments)?;
+        }
+
+        let mut node = &mut self.root;
+        let mut names = Vec::new();
+        for segment in segments.iter().cloned() {
+            node = match segment {
+                Segment::Constant(s) => node.constants.entry(s).or_default(),
+                Segment::Variable(name, pattern) => {
+                    names.push(name);
+                    node.variable(pattern)
+                }
+                Segment::Tail(name) => {
+                    names.push(name);
+                    node.tail.get_or_insert_with(Box::default)
+                }
+            };
+        }
+
+        if node.routes.iter().any(|r| r.method == endpoint.verb()) {
+            return Err(Error::DuplicateEndpoint);
+        }
+
+        node.routes.push(Route { method: endpoint.verb(), names, handler });
+        if let Some(name) = endpoint.name() {
+            Arc::make_mut(&mut self.named).resources.insert(name.to_string(), segments);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn add_mounted_names(&mut self, prefix: &str, named: &NamedEndpoints) -> Result<(), Error> {
+        let prefix = to_segments(prefix)?;
+        for (name, resource) in &named.resources {
+            let segments: Vec<_> = prefix.iter().chain(resource).cloned().collect();
+            self.named.check(name, &segments)?;
+            Arc::make_mut(&mut self.named).resources.insert(name.clone(), segments);
+        }
+        Ok(())
     }
 
-    pub fn add(&mut self, endpoint: Endpoint, handler: Box<dyn EndpointResponder + Send + Sync>) {
-        self.endpoints.push((EndpointURI::from(&endpoint), handler));
+    pub fn named(&self) -> &Arc<NamedEndpoints> {
+        &self.named
     }
 
-    pub fn find_match(&self, method: Method, url: &URL) -> Option<(&Box<dyn EndpointResponder + Send + Sync>, Bindings)> {
-        let segments: Vec<_> = url.resource().iter().map(|s| Segment::Constant(s.to_string())).collect();
+    /// Finds the endpoint for a request. A `HEAD` request is given the `GET` endpoint for its path if it has no
+    /// endpoint of its own, and the body of the response should be left out when it is sent.
+    pub fn find_match(&self, method: Method, url: &URL) -> Option<(&Handler, Bindings)> {
+        let mut values = Vec::new();
+        let route = self.root.find(url.resource(), &mut values, &|route| route.method == method)
+            .or_else(|| match method {
+                Method::HEAD => self.root.find(url.resource(), &mut values, &|route| route.method == Method::GET),
+                _ => None,
+            })?;
+
+        let bindings = route.names.iter().cloned().zip(values).collect();
+        Some((&route.handler, bindings))
+    }
+
+    /// The methods with an endpoint matching `url`, in the form of an `Allow` header, along with `HEAD` wherever
+    /// `GET` is allowed and `OPTIONS`. Empty if no endpoint matches at all.
+    pub fn allowed_methods(&self, url: &URL) -> Vec<Method> {
+        let mut methods = Vec::new();
+        self.root.methods(url.resource(), &mut methods);
+        if methods.is_empty() {
+            return methods;
+        }
+
+        if methods.contains(&Method::GET) {
+            methods.push(Method::HEAD);
+        }
+        methods.push(Method::OPTIONS);
+        methods.sort();
+        methods.dedup();
+        methods
+    }
+}
+
+impl Default for EndpointTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub type Bindings = HashMap<String, String>;
+
+/// The resources of the endpoints given names, from which URLs to them can be built.
+#[derive(Debug, Clone, Default)]
+pub struct NamedEndpoints {
+    resources: HashMap<String, Vec<Segment>>,
+}
+
+impl NamedEndpoints {
+    /// Builds the URL of the endpoint called `name` with its variables replaced by `bindings`. Fails with
+    /// `Error::UnknownEndpoint` if there is no such endpoint, or with `Error::InvalidBindings` if a variable is
+    /// missing from `bindings`, its value would not match the variable, or `bindings` has values for variables
+    /// the endpoint does not have.
+    pub fn url_for(&self, name: &str, bindings: &Bindings) -> Result<URL, Error> {
+        let segments = self.resources.get(name).ok_or(Error::UnknownEndpoint)?;
 
-        let candidates: Vec<_> = self.endpoints.iter()
-            .filter(|(e, _)| e.method == method)
-            .filter(|(e, _)| e.segments.len() == segments.len())
-            .map(|(e, h)| (e, h, e.try_match(&segments)))
-            .filter(|(_, _, bindings)| bindings.is_some())
-            .collect();
+        let mut url = URL::new();
+        let mut used = 0;
+        for segment in segments {
+            match segment {
+                Segment::Constant(s) => url.push(s.as_str()),
+                Segment::Variable(name, pattern) => {
+                    let value = bindings.get(name)
+                        .filter(|v| !v.is_empty() && pattern.matches(v))
+                        .ok_or(Error::InvalidBindings)?;
+                    url.push(value.as_str());
+                    used += 1;
+                }
+                Segment::Tail(name) => {
+                    let value = bindings.get(name).ok_or(Error::InvalidBindings)?;
+                    for s in value.split('/').filter(|s| !s.is_empty()) {
+                        url.push(s);
+                    }
+                    used += 1;
+                }
+            }
+        }
 
-        if candidates.len() == 0 {
-            return None;
-        } else if candidates.len() > 1 {
-            eprintln!("Ambiguous endpoint match! Possible to match {:?} {} to the following endpoints: {:?}",
-                      method, url.as_string().unwrap(),
-                      candidates.iter()
-                          .map(|(e, _, _)|
-                              e.segments.iter().map(
-                                  |s| match s {
-                                      Segment::Constant(s) => s.clone(),
-                                      Segment::Variable(s) => format!("<{}>", s),
-                                  }
-                              )
-                                  .fold(String::from(""), |a, b| format!("{}/{}", a, b))
-                          )
-                          .collect::<Vec<_>>());
+        if used != bindings.len() {
+            return Err(Error::InvalidBindings);
         }
 
-        return Some((candidates[0].1, candidates[0].2.clone().unwrap()));
+        Ok(url)
+    }
+
+    fn check(&self, name: &str, segments: &[Segment]) -> Result<(), Error> {
+        match self.resources.get(name) {
+            Some(existing) if existing.as_slice() != segments => Err(Error::DuplicateEndpoint),
+            _ => Ok(()),
+        }
     }
 }
 
-#[derive(Debug)]
-struct EndpointURI {
+#[derive(Default)]
+struct Node {
+    constants: HashMap<String, Node>,
+    variables: Vec<(Pattern, Node)>,
+    tail: Option<Box<Node>>,
+    routes: Vec<Route>,
+}
+
+struct Route {
     method: Method,
-    segments: Vec<Segment>,
+    names: Vec<String>,
+    handler: Handler,
 }
 
-impl EndpointURI {
-    pub fn from(endpoint: &Endpoint) -> Self {
-        Self {
-            method: endpoint.method,
-            segments: to_segments(endpoint.resource()),
+impl Node {
+    fn variable(&mut self, pattern: Pattern) -> &mut Node {
+        let index = match self.variables.iter().position(|(p, _)| *p == pattern) {
+            Some(index) => index,
+            None => {
+                let index = self.variables.iter()
+                    .position(|(p, _)| p.rank() > pattern.rank())
+                    .unwrap_or(self.variables.len());
+                self.variables.insert(index, (pattern, Node::default()));
+                index
+            }
+        };
+
+        &mut self.variables[index].1
+    }
+
+    fn find<'a>(&'a self, path: &[String], values: &mut Vec<String>, accept: &dyn Fn(&Route) -> bool) -> Option<&'a Route> {
+        let (segment, rest) = match path.split_first() {
+            Some(split) => split,
+            None => {
+                if let Some(route) = self.routes.iter().find(|r| accept(r)) {
+                    return Some(route);
+                }
+                return self.find_tail(path, values, accept);
+            }
+        };
+
+        if let Some(route) = self.constants.get(segment).and_then(|node| node.find(rest, values, accept)) {
+            return Some(route);
+        }
+
+        for (pattern, node) in &self.variables {
+            if pattern.matches(segment) {
+                values.push(segment.clone());
+                match node.find(rest, values, accept) {
+                    Some(route) => return Some(route),
+                    None => values.pop(),
+                };
+            }
         }
+
+        self.find_tail(path, values, accept)
     }
 
-    pub fn try_match(&self, other: &Vec<Segment>) -> Option<Bindings> {
-        let mut bindings = Bindings::new();
+    fn methods(&self, path: &[String], methods: &mut Vec<Method>) {
+        match path.split_first() {
+            Some((segment, rest)) => {
+                if let Some(node) = self.constants.get(segment) {
+                    node.methods(rest, methods);
+                }
 
-        for (a, b) in self.segments.iter().zip(other.iter()) {
-            match Segment::try_match(a, b, &mut bindings) {
-                false => return None,
-                true => (),
+                for (pattern, node) in &self.variables {
+                    if pattern.matches(segment) {
+                        node.methods(rest, methods);
+                    }
+                }
             }
+            None => methods.extend(self.routes.iter().map(|r| r.method)),
         }
 
-        return Some(bindings);
+        if let Some(tail) = &self.tail {
+            methods.extend(tail.routes.iter().map(|r| r.method));
+        }
     }
-}
 
-pub type Bindings = HashMap<String, String>;
+    fn find_tail<'a>(&'a self, path: &[String], values: &mut Vec<String>, accept: &dyn Fn(&Route) -> bool) -> Option<&'a Route> {
+        let route = self.tail.as_ref()?.routes.iter().find(|r| accept(r))?;
+        values.push(path.join("/"));
+        Some(route)
+    }
+}
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 enum Segment {
     Constant(String),
-    Variable(String),
+    Variable(String, Pattern),
+    Tail(String),
 }
 
-impl Segment {
-    pub fn try_match(a: &Segment, b: &Segment, bindings: &mut Bindings) -> bool {
-        use Segment::*;
+#[derive(Debug, Clone)]
+enum Pattern {
+    Any,
+    Typed(&'static str, Check),
+    Regex(Regex),
+}
 
-        match (a, b) {
-            (Constant(a), Constant(b)) => a == b,
-            (Variable(_), Variable(_)) => true,
-            (Variable(v), Constant(s)) | (Constant(s), Variable(v)) => {
-                bindings.insert(v.clone(), s.clone());
-                true
-            }
+type Check = fn(&str) -> bool;
+
+const TYPES: &[(&str, Check)] = &[
+    ("u8", |s| s.parse::<u8>().is_ok()),
+    ("u16", |s| s.parse::<u16>().is_ok()),
+    ("u32", |s| s.parse::<u32>().is_ok()),
+    ("u64", |s| s.parse::<u64>().is_ok()),
+    ("usize", |s| s.parse::<usize>().is_ok()),
+    ("i8", |s| s.parse::<i8>().is_ok()),
+    ("i16", |s| s.parse::<i16>().is_ok()),
+    ("i32", |s| s.parse::<i32>().is_ok()),
+    ("i64", |s| s.parse::<i64>().is_ok()),
+    ("isize", |s| s.parse::<isize>().is_ok()),
+    ("f32", |s| s.parse::<f32>().is_ok()),
+    ("f64", |s| s.parse::<f64>().is_ok()),
+    ("bool", |s| s.parse::<bool>().is_ok()),
+];
+
+impl Pattern {
+    fn parse(pattern: &str) -> Result<Self, Error> {
+        if let Some((name, check)) = TYPES.iter().find(|(name, _)| *name == pattern) {
+            return Ok(Pattern::Typed(name, *check));
+        }
+
+        Regex::new(&format!("^(?:{})$", pattern))
+            .map(Pattern::Regex)
+            .map_err(|_| Error::InvalidEndpoint)
+    }
+
+    fn matches(&self, segment: &str) -> bool {
+        match self {
+            Pattern::Any => true,
+            Pattern::Typed(_, check) => check(segment),
+            Pattern::Regex(regex) => regex.is_match(segment),
+        }
+    }
+
+    fn rank(&self) -> u8 {
+        match self {
+            Pattern::Typed(..) | Pattern::Regex(_) => 0,
+            Pattern::Any => 1,
+        }
+    }
+}
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Pattern::Any, Pattern::Any) => true,
+            (Pattern::Typed(a, _), Pattern::Typed(b, _)) => a == b,
+            (Pattern::Regex(a), Pattern::Regex(b)) => a.as_str() == b.as_str(),
+            _ => false,
         }
     }
 }
@@ -102,20 +333,175 @@ impl Segment {
 impl Display for Segment {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
-            Segment::Constant(s) | Segment::Variable(s) => write!(f, "{}", s),
+            Segment::Constant(s) => write!(f, "{}", s),
+            Segment::Variable(name, Pattern::Any) => write!(f, "<{}>", name),
+            Segment::Variable(name, Pattern::Typed(ty, _)) => write!(f, "<{}:{}>", name, ty),
+            Segment::Variable(name, Pattern::Regex(regex)) => {
+                let regex = regex.as_str();
+                write!(f, "<{}:{}>", name, &regex["^(?:".len()..regex.len() - ")$".len()])
+            }
+            Segment::Tail(name) => write!(f, "<{}..>", name),
         }
     }
 }
 
-fn to_segments(str: &str) -> Vec<Segment> {
-    str.split("/")
-        .skip(1)
+fn to_segments(str: &str) -> Result<Vec<Segment>, Error> {
+    let segments = str.split("/")
+        .filter(|s| !s.is_empty())
         .map(|s| {
-            if s.starts_with("<") && s.ends_with(">") {
-                Segment::Variable(s[1..s.len() - 1].to_string())
-            } else {
-                Segment::Constant(s.to_string())
+            let variable = match s.strip_prefix('<').and_then(|s| s.strip_suffix('>')) {
+                Some(variable) => variable,
+                None => return Ok(Segment::Constant(s.to_string())),
+            };
+
+            if let Some(name) = variable.strip_suffix("..") {
+                return Ok(Segment::Tail(name.to_string()));
+            }
+
+            match variable.split_once(':') {
+                Some((name, pattern)) => Ok(Segment::Variable(name.to_string(), Pattern::parse(pattern)?)),
+                None => Ok(Segment::Variable(variable.to_string(), Pattern::Any)),
             }
         })
-        .collect()
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    let mut names = Vec::new();
+    for (i, segment) in segments.iter().enumerate() {
+        let name = match segment {
+            Segment::Constant(_) => continue,
+            Segment::Tail(_) if i + 1 != segments.len() => return Err(Error::InvalidEndpoint),
+            Segment::Variable(name, _) | Segment::Tail(name) => name,
+        };
+
+        if name.is_empty() || names.contains(&name) {
+            return Err(Error::InvalidEndpoint);
+        }
+        names.push(name);
+    }
+
+    Ok(segments)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::http::StatusCode;
+
+    struct Named(&'static str);
+
+    impl EndpointResponder for Named {
+        fn response(&self, _: Request, _: Bindings) -> Response {
+            Response::new(StatusCode::OK).with_header("X-Endpoint", self.0)
+        }
+    }
+
+    fn table(resources: &[&'static str]) -> EndpointTable {
+        let mut table = EndpointTable::new();
+        for resource in resources {
+            table.add(Endpoint::new(Method::GET, resource), Box::new(Named(resource))).unwrap();
+        }
+        table
+    }
+
+    fn route(table: &EndpointTable, path: &str) -> Option<(String, Bindings)> {
+        let url = URL::from_string(path).unwrap();
+        let (handler, bindings) = table.find_match(Method::GET, &url)?;
+        let req = Request::new(Method::GET, url);
+        let endpoint = handler.response(req, Bindings::new()).header().get_first("X-Endpoint").unwrap().to_string();
+        Some((endpoint, bindings))
+    }
+
+    fn bindings(pairs: &[(&str, &str)]) -> Bindings {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn precedence() {
+        let table = table(&[
+            "/users/<name>", "/users/<id:u64>", "/users/me", "/users/<rest..>", "/<a>/<b>/c",
+            "/posts/<id:u64>/edit", "/posts/<slug>/view",
+        ]);
+
+        assert_eq!(route(&table, "/users/me"), Some(("/users/me".to_string(), bindings(&[]))));
+        assert_eq!(route(&table, "/users/42"), Some(("/users/<id:u64>".to_string(), bindings(&[("id", "42")]))));
+        assert_eq!(route(&table, "/users/bob"), Some(("/users/<name>".to_string(), bindings(&[("name", "bob")]))));
+        assert_eq!(route(&table, "/users"), Some(("/users/<rest..>".to_string(), bindings(&[("rest", "")]))));
+
+        // Precedence is decided segment by segment, so a constant early in the path wins over later ones.
+        assert_eq!(route(&table, "/users/me/c"), Some(("/users/<rest..>".to_string(), bindings(&[("rest", "me/c")]))));
+        assert_eq!(route(&table, "/x/y/c"), Some(("/<a>/<b>/c".to_string(), bindings(&[("a", "x"), ("b", "y")]))));
+
+        // A more specific segment which leads nowhere falls back to the less specific ones beside it.
+        assert_eq!(route(&table, "/posts/1/view"), Some(("/posts/<slug>/view".to_string(), bindings(&[("slug", "1")]))));
+        assert_eq!(route(&table, "/posts/1/edit"), Some(("/posts/<id:u64>/edit".to_string(), bindings(&[("id", "1")]))));
+        assert_eq!(route(&table, "/other"), None);
+    }
+
+    #[test]
+    fn constrained() {
+        let table = table(&["/files/<path..>", "/tags/<tag:[a-z]+>", "/on/<flag:bool>"]);
+
+        assert_eq!(route(&table, "/files/a/b%20c.txt").unwrap().1, bindings(&[("path", "a/b c.txt")]));
+        assert_eq!(route(&table, "/tags/rust").unwrap().1, bindings(&[("tag", "rust")]));
+        assert_eq!(route(&table, "/tags/Rust"), None);
+        assert_eq!(route(&table, "/on/true").unwrap().1, bindings(&[("flag", "true")]));
+        assert_eq!(route(&table, "/on/yes"), None);
+    }
+
+    #[test]
+    fn methods() {
+        let mut table = table(&["/users/<id:u64>"]);
+        table.add(Endpoint::new(Method::DELETE, "/users/<name>"), Box::new(Named("delete"))).unwrap();
+        table.add(Endpoint::new(Method::POST, "/users/<rest..>"), Box::new(Named("post"))).unwrap();
+
+        let methods = |path| table.allowed_methods(&URL::from_string(path).unwrap());
+        assert_eq!(methods("/users/1"), vec![Method::GET, Method::HEAD, Method::POST, Method::DELETE, Method::OPTIONS]);
+        assert_eq!(methods("/users/bob"), vec![Method::POST, Method::DELETE, Method::OPTIONS]);
+        assert_eq!(methods("/posts"), vec![]);
+
+        assert!(table.find_match(Method::HEAD, &URL::from_string("/users/1").unwrap()).is_some());
+        assert!(table.find_match(Method::HEAD, &URL::from_string("/users/bob").unwrap()).is_none());
+    }
+
+    #[test]
+    fn reverse() {
+        let mut table = EndpointTable::new();
+        let mut add = |name: &str, resource: &str| {
+            table.add(Endpoint::new(Method::GET, resource).with_name(name), Box::new(Named("")))
+        };
+        add("user", "/users/<id:u64>").unwrap();
+        add("file", "/files/<path..>").unwrap();
+        assert!(matches!(add("user", "/people/<id:u64>"), Err(Error::DuplicateEndpoint)));
+
+        let mut mounted = EndpointTable::new();
+        mounted.add(Endpoint::new(Method::GET, "/<page>").with_name("page"), Box::new(Named(""))).unwrap();
+        table.add_mounted_names("/docs/<version>", mounted.named()).unwrap();
+
+        let url_for = |name, pairs: &[(&str, &str)]| {
+            table.named().url_for(name, &bindings(pairs)).map(|url| url.target())
+        };
+        assert_eq!(url_for("user", &[("id", "7")]).unwrap(), "/users/7");
+        assert_eq!(url_for("file", &[("path", "a b/ü.txt")]).unwrap(), "/files/a%20b/%C3%BC.txt");
+        assert_eq!(url_for("page", &[("version", "1.0"), ("page", "a/b")]).unwrap(), "/docs/1.0/a%2Fb");
+
+        assert!(matches!(url_for("user", &[]), Err(Error::InvalidBindings)));
+        assert!(matches!(url_for("user", &[("id", "x")]), Err(Error::InvalidBindings)));
+        assert!(matches!(url_for("user", &[("id", "7"), ("extra", "1")]), Err(Error::InvalidBindings)));
+        assert!(matches!(url_for("missing", &[]), Err(Error::UnknownEndpoint)));
+    }
+
+    #[test]
+    fn conflicts() {
+        let mut table = table(&["/users/<id:u64>", "/files/<path..>"]);
+        let mut add = |resource: &str| table.add(Endpoint::new(Method::GET, resource), Box::new(Named("")));
+
+        assert!(matches!(add("/users/<user:u64>"), Err(Error::DuplicateEndpoint)));
+        assert!(matches!(add("/files/<other..>"), Err(Error::DuplicateEndpoint)));
+        assert!(add("/users/<id:[0-9]+>").is_ok());
+        assert!(add("/users/<id>").is_ok());
+
+        assert!(matches!(add("/<path..>/edit"), Err(Error::InvalidEndpoint)));
+        assert!(matches!(add("/<a>/<a>"), Err(Error::InvalidEndpoint)));
+        assert!(matches!(add("/<a:(>"), Err(Error::InvalidEndpoint)));
+    }
 }
@@ -0,0 +1,279 @@
+//! Typed inputs for endpoints. Wrapping a closure in `extract` lets it take any number of values implementing
+//! `FromRequest` instead of the request and its bindings, and a request any of them cannot be extracted from is
+//! answered with `400 Bad Request` without calling the closure.
+//!
+//! ```
+//! use http::http::extract::{extract, Path, Query};
+//! use http::http::{Method, Response, StatusCode, WebServer};
+//! use serde::Deserialize;
+//!
+//! #[derive(Deserialize)]
+//! struct Search { q: String }
+//!
+//! WebServer::new()
+//!     .with_endpoint(Method::GET, "/users/<id:u64>/posts", extract(|Path(id): Path<u64>, Query(search): Query<Search>| {
+//!         Response::from_text(StatusCode::OK, "text/plain", &format!("{} {}", id, search.q))
+//!     }));
+//! ```
+
+use std::fmt::Display;
+use std::marker::PhantomData;
+
+use serde::de::DeserializeOwned;
+
+use super::{Bindings, CookieJar, EndpointResponder, Header, IntoResponse, Request, Response, StatusCode};
+
+/// Something which can be taken from a request and the bindings of its endpoint.
+pub trait FromRequest: Sized {
+    fn from_request(request: &Request, bindings: &Bindings) -> Result<Self, Rejection>;
+}
+
+/// Why a value could not be extracted from a request, sent to the client as a plain text response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rejection {
+    code: StatusCode,
+    message: String,
+}
+
+impl Rejection {
+    pub fn new(code: StatusCode, message: impl Display) -> Self {
+        Self { code, message: message.to_string() }
+    }
+
+    pub fn bad_request(message: impl Display) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, message)
+    }
+
+    pub fn code(&self) -> StatusCode {
+        self.code
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl IntoResponse for Rejection {
+    fn into_response(self) -> Response {
+        Response::from_text(self.code, "text/plain; charset=utf-8", &self.message)
+    }
+}
+
+pub struct Extract<F, Args> {
+    handler: F,
+    args: PhantomData<fn() -> Args>,
+}
+
+/// Makes an endpoint from a closure whose arguments all implement `FromRequest`.
+pub fn extract<F, Args>(handler: F) -> Extract<F, Args> {
+    Extract { handler, args: PhantomData }
+}
+
+macro_rules! extract_endpoint {
+    ($($arg:ident),*) => {
+        impl<F, R, $($arg),*> EndpointResponder for Extract<F, ($($arg,)*)>
+            where F: Fn($($arg),*) -> R, R: IntoResponse, $($arg: FromRequest),*
+        {
+            #[allow(non_snake_case, unused_variables)]
+            fn response(&self, request: Request, bindings: Bindings) -> Response {
+                $(
+                    let $arg = match $arg::from_request(&request, &bindings) {
+                        Ok(value) => value,
+                        Err(rejection) => return rejection.into_response(),
+                    };
+                )*
+                (self.handler)($($arg),*).into_response()
+            }
+        }
+    };
+}
+
+extract_endpoint!();
+extract_endpoint!(A);
+extract_endpoint!(A, B);
+extract_endpoint!(A, B, C);
+extract_endpoint!(A, B, C, D);
+extract_endpoint!(A, B, C, D, E);
+extract_endpoint!(A, B, C, D, E, G);
+
+impl FromRequest for Request {
+    fn from_request(request: &Request, _: &Bindings) -> Result<Self, Rejection> {
+        Ok(request.clone())
+    }
+}
+
+impl FromRequest for Bindings {
+    fn from_request(_: &Request, bindings: &Bindings) -> Result<Self, Rejection> {
+        Ok(bindings.clone())
+    }
+}
+
+impl FromRequest for Header {
+    fn from_request(request: &Request, _: &Bindings) -> Result<Self, Rejection> {
+        Ok(request.header().clone())
+    }
+}
+
+impl FromRequest for CookieJar {
+    fn from_request(request: &Request, _: &Bindings) -> Result<Self, Rejection> {
+        Ok(CookieJar::from_header(request.header()))
+    }
+}
+
+/// Makes extracting a value optional, giving `None` rather than rejecting the request.
+impl<T: FromRequest> FromRequest for Option<T> {
+    fn from_request(request: &Request, bindings: &Bindings) -> Result<Self, Rejection> {
+        Ok(T::from_request(request, bindings).ok())
+    }
+}
+
+/// The bindings of the endpoint's variables, parsed into a struct with a field named after each variable, or into
+/// a single value if the endpoint has only one variable.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Path<T>(pub T);
+
+impl<T: DeserializeOwned> FromRequest for Path<T> {
+    fn from_request(_: &Request, bindings: &Bindings) -> Result<Self, Rejection> {
+        let encoded = serde_urlencoded::to_string(bindings).map_err(Rejection::bad_request)?;
+
+        let value = serde_urlencoded::from_str::<T>(&encoded).or_else(|e| match bindings.len() {
+            1 => serde_urlencoded::from_str::<Vec<(String, T)>>(&encoded)
+                .map(|mut pairs| pairs.remove(0).1)
+                .map_err(|_| e),
+            _ => Err(e),
+        });
+
+        value.map(Path).map_err(|e| Rejection::bad_request(format!("Invalid path: {}", e)))
+    }
+}
+
+/// The parameters of the query, parsed into a struct with a field named after each parameter.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Query<T>(pub T);
+
+impl<T: DeserializeOwned> FromRequest for Query<T> {
+    fn from_request(request: &Request, _: &Bindings) -> Result<Self, Rejection> {
+        let encoded = serde_urlencoded::to_string(request.url().params()).map_err(Rejection::bad_request)?;
+        serde_urlencoded::from_str(&encoded)
+            .map(Query)
+            .map_err(|e| Rejection::bad_request(format!("Invalid query: {}", e)))
+    }
+}
+
+/// A JSON body, which must be sent with a `Content-Type` of `application/json`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Json<T>(pub T);
+
+impl<T: DeserializeOwned> FromRequest for Json<T> {
+    fn from_request(request: &Request, _: &Bindings) -> Result<Self, Rejection> {
+        expect_content_type(request, "application/json")?;
+        serde_json::from_slice(request.body())
+            .map(Json)
+            .map_err(|e| Rejection::bad_request(format!("Invalid JSON body: {}", e)))
+    }
+}
+
+/// A form body, which must be sent with a `Content-Type` of `application/x-www-form-urlencoded`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Form<T>(pub T);
+
+impl<T: DeserializeOwned> FromRequest for Form<T> {
+    fn from_request(request: &Request, _: &Bindings) -> Result<Self, Rejection> {
+        expect_content_type(request, "application/x-www-form-urlencoded")?;
+        serde_urlencoded::from_bytes(request.body())
+            .map(Form)
+            .map_err(|e| Rejection::bad_request(format!("Invalid form body: {}", e)))
+    }
+}
+
+fn expect_content_type(request: &Request, expected: &str) -> Result<(), Rejection> {
+    let essence = request.header().content_type()
+        .and_then(|t| t.split(';').next())
+        .map(|t| t.trim());
+
+    match essence {
+        Some(essence) if essence.eq_ignore_ascii_case(expected) => Ok(()),
+        _ => Err(Rejection::bad_request(format!("Expected a body of type {}", expected))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::http::Method;
+    use crate::url::URL;
+    use serde::Deserialize;
+
+    #[derive(Deserialize)]
+    struct Post {
+        user: u64,
+        post: String,
+    }
+
+    #[derive(Deserialize)]
+    struct Search {
+        q: String,
+        page: Option<u32>,
+    }
+
+    fn call(endpoint: &impl EndpointResponder, request: Request, bindings: &[(&str, &str)]) -> (StatusCode, String) {
+        let bindings = bindings.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        let response = endpoint.response(request, bindings);
+        let body = match response.body() {
+            crate::http::Body::Bytes(bytes) => String::from_utf8(bytes.clone()).unwrap(),
+            _ => unreachable!(),
+        };
+        (response.code(), body)
+    }
+
+    fn get(url: &str) -> Request {
+        Request::new(Method::GET, URL::from_string(url).unwrap())
+    }
+
+    fn text(s: String) -> Response {
+        Response::from_text(StatusCode::OK, "text/plain", &s)
+    }
+
+    #[test]
+    fn path_and_query() {
+        let single = extract(|Path(id): Path<u64>| text(id.to_string()));
+        assert_eq!(call(&single, get("/"), &[("id", "42")]), (StatusCode::OK, "42".to_string()));
+        assert_eq!(call(&single, get("/"), &[("id", "x")]).0, StatusCode::BAD_REQUEST);
+
+        let both = extract(|Path(path): Path<Post>, Query(search): Query<Search>| {
+            text(format!("{} {} {} {:?}", path.user, path.post, search.q, search.page))
+        });
+        assert_eq!(
+            call(&both, get("/?q=a%20b&page=2"), &[("user", "1"), ("post", "hi")]),
+            (StatusCode::OK, "1 hi a b Some(2)".to_string())
+        );
+        assert_eq!(call(&both, get("/?page=2"), &[("user", "1"), ("post", "hi")]).0, StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn bodies() {
+        let json = extract(|Json(search): Json<Search>| text(search.q));
+        let request = get("/").with_body("application/json", br#"{"q": "rust"}"#.to_vec());
+        assert_eq!(call(&json, request, &[]), (StatusCode::OK, "rust".to_string()));
+        let request = get("/").with_body("text/plain", br#"{"q": "rust"}"#.to_vec());
+        assert_eq!(call(&json, request, &[]).0, StatusCode::BAD_REQUEST);
+
+        let form = extract(|Form(search): Form<Search>, cookies: CookieJar| {
+            text(format!("{} {}", search.q, cookies.get("theme").unwrap_or("none")))
+        });
+        let request = get("/")
+            .with_header("Cookie", "theme=dark")
+            .with_body("application/x-www-form-urlencoded", b"q=a+b".to_vec());
+        assert_eq!(call(&form, request, &[]), (StatusCode::OK, "a b dark".to_string()));
+    }
+
+    #[test]
+    fn closures() {
+        let endpoint = |_: Request, bindings: Bindings| -> Result<Response, Rejection> {
+            let name = bindings.get("name").ok_or_else(|| Rejection::bad_request("No name"))?;
+            Ok(text(name.clone()))
+        };
+        assert_eq!(call(&endpoint, get("/"), &[("name", "bob")]), (StatusCode::OK, "bob".to_string()));
+        assert_eq!(call(&endpoint, get("/"), &[]), (StatusCode::BAD_REQUEST, "No name".to_string()));
+    }
+}
@@ -1,4 +1,10 @@
 use std::borrow::Borrow;
+use std::collections::HashMap;
+
+use super::cookie::parse_cookie_header;
+use super::CookieKeys;
+use super::Error;
+use crate::log;
 
 #[derive(Debug, Clone)]
 pub struct Header {
@@ -14,24 +20,42 @@ impl Header {
 
     pub fn get_first(&self, key: impl Borrow<str>) -> Option<&str> {
         self.data.iter()
-            .find(|h| &h.key == key.borrow())
+            .find(|h| h.key.eq_ignore_ascii_case(key.borrow()))
             .map(|h| h.value.as_str())
     }
 
     pub fn get_all(&self, key: impl Borrow<str>) -> Vec<&str> {
         self.data.iter()
-            .filter(|h| &h.key == key.borrow())
+            .filter(|h| h.key.eq_ignore_ascii_case(key.borrow()))
             .map(|h| h.value.as_str())
             .collect()
     }
 
+    /// Whether any of the comma-separated values of the header `key` is `token`, ignoring case.
+    pub fn contains_token(&self, key: impl Borrow<str>, token: &str) -> bool {
+        self.get_all(key)
+            .into_iter()
+            .flat_map(|v| v.split(','))
+            .any(|v| v.trim().eq_ignore_ascii_case(token))
+    }
+
+    /// Appends a field, keeping any others with the same name.
+    /// A field whose name is not a valid token or whose value contains CR, LF or NUL is dropped with a warning, use
+    /// `try_add` to handle it instead.
     pub fn add(&mut self, key: impl Borrow<str>, value: impl Borrow<str>) {
-        let entry = HeaderEntry { key: key.borrow().to_owned(), value: value.borrow().to_owned() };
+        if self.try_add(key.borrow(), value.borrow()).is_err() {
+            log::warn(format!("Dropped invalid header field {:?}: {:?}", key.borrow(), value.borrow()));
+        }
+    }
 
-        match self.data.binary_search(&entry) {
-            Ok(_) => (),
-            Err(pos) => self.data.insert(pos, entry),
+    pub fn try_add(&mut self, key: impl Borrow<str>, value: impl Borrow<str>) -> Result<(), Error> {
+        let (key, value) = (key.borrow(), value.borrow());
+        if !is_valid_name(key) || !is_valid_value(value) {
+            return Err(Error::InvalidHeader);
         }
+
+        self.data.push(HeaderEntry { key: key.to_owned(), value: value.to_owned() });
+        Ok(())
     }
 
     pub fn replace(&mut self, key: impl Borrow<str>, value: impl Borrow<str>) {
@@ -40,18 +64,97 @@ impl Header {
     }
 
     pub fn remove(&mut self, key: impl Borrow<str>) {
-        self.data.retain(|h| h.key != key.borrow())
+        self.data.retain(|h| !h.key.eq_ignore_ascii_case(key.borrow()))
+    }
+
+    /// The length of the body, or `None` if it is missing, malformed or given more than once with different values.
+    pub fn content_length(&self) -> Option<u64> {
+        let mut lengths = self.get_all("Content-Length")
+            .into_iter()
+            .flat_map(|v| v.split(','))
+            .map(|v| v.trim())
+            .map(|v| if v.bytes().all(|b| b.is_ascii_digit()) { v.parse::<u64>().ok() } else { None });
+
+        let first = lengths.next()??;
+        lengths.all(|l| l == Some(first)).then_some(first)
+    }
+
+    pub fn content_type(&self) -> Option<&str> {
+        self.get_first("Content-Type")
+    }
+
+    pub fn host(&self) -> Option<&str> {
+        self.get_first("Host")
+    }
+
+    /// The acceptable media ranges of `Accept` with their quality values, most preferred first.
+    pub fn accept(&self) -> Vec<(&str, f32)> {
+        let mut ranges = self.quality_list("Accept");
+        ranges.retain(|(_, q)| *q > 0.0);
+        ranges
+    }
+
+    pub fn authorization(&self) -> Option<(&str, &str)> {
+        let value = self.get_first("Authorization")?.trim();
+        match value.split_once(' ') {
+            Some((scheme, credentials)) => Some((scheme, credentials.trim())),
+            None if !value.is_empty() => Some((value, "")),
+            None => None,
+        }
+    }
+
+    /// Parses a comma-separated list whose items may carry a `q` parameter, sorted by descending quality.
+    /// Items with a quality of zero are kept, as they mark something the client explicitly refuses.
+    pub fn quality_list(&self, key: impl Borrow<str>) -> Vec<(&str, f32)> {
+        let mut items: Vec<(&str, f32)> = self.get_all(key)
+            .into_iter()
+            .flat_map(|v| v.split(','))
+            .filter_map(|item| {
+                let mut params = item.split(';').map(|p| p.trim());
+                let value = params.next().filter(|v| !v.is_empty())?;
+                let quality = params
+                    .filter_map(|p| p.split_once('='))
+                    .find(|(k, _)| k.trim().eq_ignore_ascii_case("q"))
+                    .map(|(_, q)| q.trim().parse::<f32>().unwrap_or(0.0))
+                    .unwrap_or(1.0);
+                Some((value, quality.clamp(0.0, 1.0)))
+            })
+            .collect();
+
+        items.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+        items
     }
 
     pub fn cookie(&self, name: impl Borrow<str>) -> Option<&str> {
         self.get_all("Cookie")
             .into_iter()
-            .map(|s| s.split_once('='))
-            .filter(|x| x.is_some())
-            .map(|x| unsafe { x.unwrap_unchecked() })
-            .filter(|(key, _)| *key == name.borrow())
+            .flat_map(parse_cookie_header)
+            .find(|(key, _)| *key == name.borrow())
             .map(|(_, val)| val)
-            .nth(0)
+    }
+
+    pub fn signed_cookie(&self, name: impl Borrow<str>, keys: &CookieKeys) -> Option<String> {
+        keys.verify(name.borrow(), self.cookie(name.borrow())?)
+    }
+
+    pub fn private_cookie(&self, name: impl Borrow<str>, keys: &CookieKeys) -> Option<String> {
+        keys.decrypt(name.borrow(), self.cookie(name.borrow())?)
+    }
+
+    /// Every cookie sent in the `Cookie` headers. Browsers send the cookie with the most specific path first, so
+    /// when a name appears more than once the first value is kept.
+    pub fn cookies(&self) -> HashMap<&str, &str> {
+        let mut cookies = HashMap::new();
+        for (key, val) in self.get_all("Cookie").into_iter().flat_map(parse_cookie_header) {
+            cookies.entry(key).or_insert(val);
+        }
+        cookies
+    }
+}
+
+impl Default for Header {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -79,4 +182,60 @@ impl<'r> IntoIterator for &'r Header {
 pub struct HeaderEntry {
     key: String,
     value: String,
-}
\ No newline at end of file
+}
+
+pub(crate) fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.bytes().all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+fn is_valid_value(value: &str) -> bool {
+    !value.bytes().any(|b| b == b'\r' || b == b'\n' || b == 0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn case_insensitive_multimap() {
+        let mut header = Header::new();
+        header.add("Set-Cookie", "a=1");
+        header.add("content-length", "5");
+        header.add("Set-Cookie", "a=1");
+
+        assert_eq!(header.get_all("set-cookie"), vec!["a=1", "a=1"]);
+        assert_eq!(header.content_length(), Some(5));
+        assert_eq!((&header).into_iter().next(), Some(("Set-Cookie", "a=1")));
+
+        header.remove("SET-COOKIE");
+        assert!(header.get_first("Set-Cookie").is_none());
+    }
+
+    #[test]
+    fn rejects_splitting() {
+        let mut header = Header::new();
+        assert!(header.try_add("X-Evil", "a\r\nSet-Cookie: b=2").is_err());
+        assert!(header.try_add("X Evil", "a").is_err());
+        assert!(header.try_add("", "a").is_err());
+        assert!(header.get_first("X-Evil").is_none());
+
+        header.add("X-Evil", "a\r\nSet-Cookie: b=2");
+        header.replace("X Evil", "a");
+        assert!(header.get_first("X-Evil").is_none());
+        assert!(header.get_first("Set-Cookie").is_none());
+        assert_eq!((&header).into_iter().count(), 0);
+    }
+
+    #[test]
+    fn typed_accessors() {
+        let mut header = Header::new();
+        header.add("Content-Length", "5");
+        header.add("Content-Length", "6");
+        header.add("Accept", "text/html;q=0.5, application/json, image/*;q=0");
+        header.add("Authorization", "Bearer abc");
+
+        assert_eq!(header.content_length(), None);
+        assert_eq!(header.accept(), vec![("application/json", 1.0), ("text/html", 0.5)]);
+        assert_eq!(header.authorization(), Some(("Bearer", "abc")));
+    }
+}
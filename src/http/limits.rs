@@ -0,0 +1,62 @@
+/// Bounds on the size of requests a `Stream` will read, protecting the server from clients that try to
+/// exhaust its memory.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Limits {
+    max_request_line: usize,
+    max_header_bytes: usize,
+    max_header_count: usize,
+    max_body_size: usize,
+}
+
+impl Limits {
+    pub fn new() -> Self {
+        Self {
+            max_request_line: 8 * 1024,
+            max_header_bytes: 64 * 1024,
+            max_header_count: 100,
+            max_body_size: 16 * 1024 * 1024,
+        }
+    }
+
+    /// The longest request line accepted before answering `414 URI Too Long`.
+    pub fn with_max_request_line(self, max_request_line: usize) -> Self {
+        Self { max_request_line, ..self }
+    }
+
+    /// The most bytes of header fields accepted before answering `431 Request Header Fields Too Large`.
+    pub fn with_max_header_bytes(self, max_header_bytes: usize) -> Self {
+        Self { max_header_bytes, ..self }
+    }
+
+    /// The most header fields accepted before answering `431 Request Header Fields Too Large`.
+    pub fn with_max_header_count(self, max_header_count: usize) -> Self {
+        Self { max_header_count, ..self }
+    }
+
+    /// The largest body accepted before answering `413 Payload Too Large`.
+    pub fn with_max_body_size(self, max_body_size: usize) -> Self {
+        Self { max_body_size, ..self }
+    }
+
+    pub fn max_request_line(&self) -> usize {
+        self.max_request_line
+    }
+
+    pub fn max_header_bytes(&self) -> usize {
+        self.max_header_bytes
+    }
+
+    pub fn max_header_count(&self) -> usize {
+        self.max_header_count
+    }
+
+    pub fn max_body_size(&self) -> usize {
+        self.max_body_size
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,90 @@
+use super::{Request, Response};
+
+/// Code run around every request a `WebServer` handles, such as authentication, logging or extra headers.
+/// Middleware registered with `WebServer::with_middleware` runs `before` in the order it was registered, and
+/// `after` in the reverse order, so the response passes back out through every layer the request went in through.
+pub trait Middleware {
+    /// Inspects or modifies the request before it is handled. Returning a response skips the handler and any
+    /// later middleware, and passes the response to the `after` of this and every earlier middleware.
+    fn before(&self, _req: &mut Request) -> Option<Response> {
+        None
+    }
+
+    /// Inspects or modifies the response to a request. Once the request has been handled it is passed here without
+    /// its body, which the handler took.
+    fn after(&self, _req: &Request, res: Response) -> Response {
+        res
+    }
+}
+
+pub(super) fn apply<F>(middleware: &[Box<dyn Middleware + Send + Sync>], mut req: Request, handler: F) -> Response
+    where F: FnOnce(Request) -> Response
+{
+    let mut entered = 0;
+    let mut response = None;
+
+    for layer in middleware {
+        entered += 1;
+        response = layer.before(&mut req);
+        if response.is_some() {
+            break;
+        }
+    }
+
+    let (req, mut response) = match response {
+        Some(response) => (req, response),
+        None => (req.clone_head(), handler(req)),
+    };
+
+    for layer in middleware[..entered].iter().rev() {
+        response = layer.after(&req, response);
+    }
+
+    response
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::http::{Method, StatusCode};
+    use crate::url::URL;
+
+    struct Tag(&'static str, bool);
+
+    impl Middleware for Tag {
+        fn before(&self, req: &mut Request) -> Option<Response> {
+            *req = req.clone().with_header("X-Before", self.0);
+            if self.1 {
+                Some(Response::new(StatusCode::FORBIDDEN))
+            } else {
+                None
+            }
+        }
+
+        fn after(&self, _req: &Request, res: Response) -> Response {
+            res.with_header("X-After", self.0)
+        }
+    }
+
+    fn run(middleware: Vec<Box<dyn Middleware + Send + Sync>>) -> Response {
+        let req = Request::new(Method::GET, URL::from_string("/").unwrap());
+        apply(&middleware, req, |req| {
+            Response::new(StatusCode::OK).with_header("X-Seen", req.header().get_all("X-Before").join(","))
+        })
+    }
+
+    #[test]
+    fn ordering() {
+        let response = run(vec![Box::new(Tag("a", false)), Box::new(Tag("b", false))]);
+        assert_eq!(response.code(), StatusCode::OK);
+        assert_eq!(response.header().get_first("X-Seen"), Some("a,b"));
+        assert_eq!(response.header().get_all("X-After"), vec!["b", "a"]);
+    }
+
+    #[test]
+    fn short_circuit() {
+        let response = run(vec![Box::new(Tag("a", true)), Box::new(Tag("b", false))]);
+        assert_eq!(response.code(), StatusCode::FORBIDDEN);
+        assert_eq!(response.header().get_all("X-After"), vec!["a"]);
+    }
+}
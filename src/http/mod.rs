@@ -1,28 +1,57 @@
 // Modules
+mod body;
+mod chunked;
+mod compression;
+mod conditional;
+mod date;
 mod endpoint;
+pub mod extract;
 mod request;
 mod response;
+mod range;
+mod session;
 mod stream;
+mod timeouts;
 mod cookie;
+mod cookie_key;
 mod header;
+mod limits;
+mod middleware;
+mod status;
 
 // Exports
+pub use body::*;
+pub use compression::Compression;
 pub use header::*;
+pub use limits::*;
+pub use middleware::Middleware;
+pub use status::*;
 pub use cookie::*;
+pub use cookie_key::*;
 pub use request::*;
+pub use session::*;
 pub use response::*;
 pub use stream::*;
+pub use timeouts::*;
 pub use endpoint::*;
 
 use std::borrow::Borrow;
 use std::collections::HashMap;
 use std::convert::TryFrom;
-use std::io::{Read, Write};
+use std::io::{BufRead, Read};
 use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
 
-use crate::server::WebService;
+use crate::log::{self, AccessLog, AccessLogEntry};
+use crate::mime::extension_to_mime;
+use crate::server::{Connection, WebService};
 use crate::url::URL;
+use chunked::read_chunked;
+use conditional::{file_etag, if_range_matches, Precondition};
+use date::{format_http_date, from_system_time};
+use range::{parse_range, partial_response, unsatisfiable_response, Ranges};
 
 #[derive(Debug, PartialOrd, PartialEq, Copy, Clone, Eq, Ord)]
 pub enum Method {
@@ -37,6 +66,77 @@ pub enum Method {
     PATCH,
 }
 
+impl Method {
+    pub const ALL: &'static [Method] = &[
+        Method::GET,
+        Method::HEAD,
+        Method::POST,
+        Method::PUT,
+        Method::DELETE,
+        Method::CONNECT,
+        Method::OPTIONS,
+        Method::TRACE,
+        Method::PATCH,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::GET => "GET",
+            Self::HEAD => "HEAD",
+            Self::POST => "POST",
+            Self::PUT => "PUT",
+            Self::DELETE => "DELETE",
+            Self::CONNECT => "CONNECT",
+            Self::OPTIONS => "OPTIONS",
+            Self::TRACE => "TRACE",
+            Self::PATCH => "PATCH",
+        }
+    }
+
+    /// Whether sending a request with this method several times has the same effect as sending it once, so that it
+    /// may be retried when it is unknown whether the server received it.
+    pub fn is_idempotent(&self) -> bool {
+        matches!(self, Self::GET | Self::HEAD | Self::PUT | Self::DELETE | Self::OPTIONS | Self::TRACE)
+    }
+}
+
+#[derive(Debug, PartialOrd, PartialEq, Copy, Clone, Eq, Ord, Hash)]
+pub enum Version {
+    HTTP10,
+    HTTP11,
+}
+
+impl Version {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::HTTP10 => "HTTP/1.0",
+            Self::HTTP11 => "HTTP/1.1",
+        }
+    }
+}
+
+/// Parses an `HTTP/x.y` version token. Later minor versions of HTTP/1 are compatible with HTTP/1.1, while
+/// other major versions are rejected with `Error::UnsupportedVersion`.
+impl TryFrom<&str> for Version {
+    type Error = Error;
+
+    fn try_from(from: &str) -> Result<Self, Self::Error> {
+        let (major, minor) = from
+            .strip_prefix("HTTP/")
+            .and_then(|v| v.split_once('.'))
+            .filter(|(major, minor)| {
+                [major, minor].iter().all(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+            })
+            .ok_or(Error::RequestParse)?;
+
+        match (major.parse::<u32>(), minor.parse::<u32>()) {
+            (Ok(1), Ok(0)) => Ok(Self::HTTP10),
+            (Ok(1), Ok(_)) => Ok(Self::HTTP11),
+            _ => Err(Error::UnsupportedVersion),
+        }
+    }
+}
+
 impl TryFrom<&str> for Method {
     type Error = ();
 
@@ -64,13 +164,121 @@ pub enum Error {
     InvalidEndpoint,
     DuplicateEndpoint,
     URLParse,
+    ResponseParse,
+    URITooLong,
+    HeaderTooLarge,
+    PayloadTooLarge,
+    UnsupportedMethod,
+    UnsupportedVersion,
+    InvalidCookie,
+    Timeout,
     ConnectionClosed,
+    TooManyRedirects,
+    UnknownEndpoint,
+    InvalidBindings,
+    TLSError(openssl::ssl::Error),
+}
+
+impl Error {
+    /// The status code to answer a client with when its request failed to parse with this error, if the
+    /// connection is still in a state where a response can be sent.
+    pub fn response_code(&self) -> Option<StatusCode> {
+        match self {
+            Error::RequestParse | Error::InvalidHeader | Error::URLParse => Some(StatusCode::BAD_REQUEST),
+            Error::IOError(e) if e.kind() == std::io::ErrorKind::InvalidData => Some(StatusCode::BAD_REQUEST),
+            Error::URITooLong => Some(StatusCode::URI_TOO_LONG),
+            Error::HeaderTooLarge => Some(StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE),
+            Error::PayloadTooLarge => Some(StatusCode::CONTENT_TOO_LARGE),
+            Error::UnsupportedMethod => Some(StatusCode::NOT_IMPLEMENTED),
+            Error::UnsupportedVersion => Some(StatusCode::HTTP_VERSION_NOT_SUPPORTED),
+            Error::Timeout => Some(StatusCode::REQUEST_TIMEOUT),
+            _ => None,
+        }
+    }
+}
+
+fn read_line<R: BufRead>(reader: &mut R, line: &mut String, limit: usize, too_long: Error) -> Result<(), Error> {
+    match reader.by_ref().take(limit as u64).read_line(line) {
+        Ok(_) if line.ends_with('\n') => Ok(()),
+        Ok(n) if n >= limit => Err(too_long),
+        Ok(_) => Err(Error::ConnectionClosed),
+        Err(e) => Err(Error::IOError(e)),
+    }
+}
+
+fn read_header<R: BufRead>(reader: &mut R, limits: &Limits) -> Result<Header, Error> {
+    let mut header = Header::new();
+    let mut line = String::new();
+    let mut header_bytes = 0;
+    let mut header_count = 0;
+
+    loop {
+        line.clear();
+        read_line(reader, &mut line, limits.max_header_bytes() - header_bytes, Error::HeaderTooLarge)?;
+        header_bytes += line.len();
+
+        let field = line.trim_end_matches(&['\r', '\n'][..]);
+        if field.is_empty() {
+            return Ok(header);
+        }
+
+        header_count += 1;
+        if header_count > limits.max_header_count() {
+            return Err(Error::HeaderTooLarge);
+        }
+
+        let (key, value) = field.split_once(':').ok_or(Error::InvalidHeader)?;
+        if key.is_empty() || key.ends_with(char::is_whitespace) {
+            return Err(Error::InvalidHeader);
+        }
+
+        header.try_add(key, value.trim())?;
+    }
+}
+
+fn read_body<R: BufRead>(reader: &mut R, header: &mut Header, limits: &Limits) -> Result<Vec<u8>, Error> {
+    // Transfer-Encoding takes precedence over Content-Length, and chunked is the only coding we understand.
+    let chunked = match header.get_first("Transfer-Encoding") {
+        Some(coding) if coding.trim().eq_ignore_ascii_case("chunked") => true,
+        Some(_) => return Err(Error::InvalidHeader),
+        None => false,
+    };
+
+    if chunked {
+        let data = read_chunked(reader, header, limits)?;
+        header.remove("Transfer-Encoding");
+        header.replace("Content-Length", data.len().to_string());
+        return Ok(data);
+    }
+
+    let content_length = match header.get_first("Content-Length") {
+        Some(_) => header.content_length().ok_or(Error::InvalidHeader)?,
+        None => 0,
+    };
+    if content_length > limits.max_body_size() as u64 {
+        return Err(Error::PayloadTooLarge);
+    }
+
+    let mut data = vec![0; content_length as usize];
+    reader.read_exact(&mut data[..]).map_err(Error::IOError)?;
+    Ok(data)
+}
+
+fn precompressed_path(path: &Path) -> Option<PathBuf> {
+    let mut name = path.file_name()?.to_os_string();
+    name.push(".gz");
+    Some(path.with_file_name(name))
 }
 
 pub struct WebServer {
     root: PathBuf,
     endpoints: EndpointTable,
     file_masks: HashMap<PathBuf, Box<dyn FileResponder + Send + Sync + 'static>>,
+    limits: Limits,
+    timeouts: Timeouts,
+    max_requests: usize,
+    middleware: Vec<Box<dyn Middleware + Send + Sync>>,
+    access_log: Option<AccessLog>,
 }
 
 impl WebServer {
@@ -79,6 +287,11 @@ impl WebServer {
             root: PathBuf::from("./"),
             endpoints: EndpointTable::new(),
             file_masks: HashMap::new(),
+            limits: Limits::new(),
+            timeouts: Timeouts::new(),
+            max_requests: 1000,
+            middleware: Vec::new(),
+            access_log: None,
         }
     }
 
@@ -86,11 +299,117 @@ impl WebServer {
         Self { root: std::fs::canonicalize(root.as_ref()).unwrap(), ..self }
     }
 
-    pub fn with_endpoint<S, H>(mut self, method: Method, endpoint: &S, handler: H) -> Self
+    pub fn with_limits(self, limits: Limits) -> Self {
+        Self { limits, ..self }
+    }
+
+    pub fn with_timeouts(self, timeouts: Timeouts) -> Self {
+        Self { timeouts, ..self }
+    }
+
+    pub fn with_max_requests(self, max_requests: usize) -> Self {
+        Self { max_requests: max_requests.max(1), ..self }
+    }
+
+    pub fn with_access_log(self, access_log: AccessLog) -> Self {
+        Self { access_log: Some(access_log), ..self }
+    }
+
+    /// Adds middleware to run around every request, after any added before it.
+    pub fn with_middleware(mut self, middleware: impl Middleware + Send + Sync + 'static) -> Self {
+        self.middleware.push(Box::new(middleware));
+        self
+    }
+
+    /// Adds an endpoint, panicking if its resource is invalid or conflicts with an endpoint already added.
+    /// See `EndpointTable` for the syntax of resources.
+    pub fn with_endpoint<S, H>(self, method: Method, endpoint: &S, handler: H) -> Self
         where S: Borrow<str> + ?Sized, H: EndpointResponder + Send + Sync + 'static
     {
-        self.endpoints.add(Endpoint::new(method, endpoint.borrow()), Box::new(handler));
-        self
+        let endpoint = endpoint.borrow();
+        match self.try_with_endpoint(method, endpoint, handler) {
+            Ok(server) => server,
+            Err(e) => panic!("Failed to add endpoint {:?} {}: {:?}", method, endpoint, e),
+        }
+    }
+
+    pub fn try_with_endpoint<S, H>(mut self, method: Method, endpoint: &S, handler: H) -> Result<Self, Error>
+        where S: Borrow<str> + ?Sized, H: EndpointResponder + Send + Sync + 'static
+    {
+        self.endpoints.add(Endpoint::new(method, endpoint.borrow()), Box::new(handler))?;
+        Ok(self)
+    }
+
+    pub fn with_named_endpoint<S, H>(self, name: &str, method: Method, endpoint: &S, handler: H) -> Self
+        where S: Borrow<str> + ?Sized, H: EndpointResponder + Send + Sync + 'static
+    {
+        let endpoint = endpoint.borrow();
+        match self.try_with_named_endpoint(name, method, endpoint, handler) {
+            Ok(server) => server,
+            Err(e) => panic!("Failed to add endpoint {} {:?} {}: {:?}", name, method, endpoint, e),
+        }
+    }
+
+    pub fn try_with_named_endpoint<S, H>(mut self, name: &str, method: Method, endpoint: &S, handler: H)
+        -> Result<Self, Error>
+        where S: Borrow<str> + ?Sized, H: EndpointResponder + Send + Sync + 'static
+    {
+        self.endpoints.add(Endpoint::new(method, endpoint.borrow()).with_name(name), Box::new(handler))?;
+        Ok(self)
+    }
+
+    /// Builds the percent-encoded URL of the endpoint called `name`, with its variables replaced by `bindings`.
+    /// Endpoints are also available to handlers through `Request::url_for`. See `NamedEndpoints::url_for`.
+    pub fn url_for(&self, name: &str, bindings: &Bindings) -> Result<URL, Error> {
+        self.endpoints.named().url_for(name, bindings)
+    }
+
+    /// Adds the endpoints of a group, panicking if any are invalid or conflict with an endpoint already added.
+    pub fn with_group(self, group: EndpointGroup) -> Self {
+        match self.try_with_group(group) {
+            Ok(server) => server,
+            Err(e) => panic!("Failed to add endpoint group: {:?}", e),
+        }
+    }
+
+    pub fn try_with_group(mut self, group: EndpointGroup) -> Result<Self, Error> {
+        for (endpoint, handler) in group.into_endpoints() {
+            self.endpoints.add(endpoint, handler)?;
+        }
+        Ok(self)
+    }
+
+    /// Serves every request below `prefix` with another server, which sees the rest of the path as if it were the
+    /// whole. Its endpoints, middleware and files are used, but its limits, timeouts and access log are not.
+    /// Bindings of variables in `prefix` are passed on to the endpoints of `server`, and URLs to its named
+    /// endpoints can be built with this server's `url_for`. Panics if `prefix` is invalid or something is already
+    /// mounted there.
+    pub fn with_mount<S>(self, prefix: &S, server: WebServer) -> Self
+        where S: Borrow<str> + ?Sized
+    {
+        let prefix = prefix.borrow();
+        match self.try_with_mount(prefix, server) {
+            Ok(server) => server,
+            Err(e) => panic!("Failed to mount server at {}: {:?}", prefix, e),
+        }
+    }
+
+    pub fn try_with_mount<S>(mut self, prefix: &S, server: WebServer) -> Result<Self, Error>
+        where S: Borrow<str> + ?Sized
+    {
+        let prefix = prefix.borrow();
+        self.endpoints.add_mounted_names(prefix, server.endpoints.named())?;
+
+        let mount = Mount {
+            depth: prefix.split('/').filter(|s| !s.is_empty()).count(),
+            server: Arc::new(server),
+        };
+
+        let resource = format!("{}/<{}..>", prefix, Mount::TAIL);
+        for &method in Method::ALL {
+            self.endpoints.add(Endpoint::new(method, &resource), Box::new(mount.clone()))?;
+        }
+        Ok(self)
     }
 
     pub fn with_file_mask<P, H>(mut self, file: &P, handler: H) -> Self
@@ -109,17 +428,80 @@ impl WebServer {
             return Some(handler.response(req, path));
         }
 
-        return match &req.method() {
-            Method::GET => {
-                Response::from_file(200, None, path).ok()
+        // A gzipped copy of the file saved alongside it is served instead to clients which accept gzip, so it
+        // need not be compressed for every request. Ranges always refer to the file itself.
+        let precompressed = precompressed_path(&path).filter(|p| p.is_file());
+        let vary = precompressed.is_some();
+        let gzip = precompressed.filter(|_| {
+            req.header().get_first("Range").is_none() && compression::negotiate(req.header(), &["gzip"]).is_some()
+        });
+        let served = gzip.as_deref().unwrap_or(&path);
+
+        let metadata = std::fs::metadata(served).ok()?;
+        let last_modified = metadata.modified().ok().map(from_system_time);
+        let etag = file_etag(metadata.len(), last_modified);
+
+        let response = match conditional::evaluate(&req, &etag, last_modified) {
+            Precondition::Proceed => match &req.method() {
+                Method::GET | Method::HEAD => {
+                    let mime = extension_to_mime(path.extension().and_then(|s| s.to_str()).unwrap_or(""));
+                    let response = self.serve_file(&req, served, mime, metadata.len(), &etag, last_modified).ok()?;
+                    match gzip {
+                        Some(_) => response.with_header("Content-Encoding", "gzip"),
+                        None => response,
+                    }
+                }
+
+                Method::TRACE => {
+                    Response::from_file(StatusCode::OK, None, &path).ok()?.with_body("application/octet-stream", Vec::new())
+                }
+
+                _ => return None,
+            },
+
+            Precondition::NotModified => {
+                let response = Response::new(StatusCode::NOT_MODIFIED).without_header("Content-Length");
+                WebServer::with_validators(response, &etag, last_modified)
             }
 
-            Method::TRACE => {
-                Some(Response::from_file(200, None, path).ok()?.with_body("application/octet-stream", Vec::new()))
+            Precondition::Failed => Response::new(StatusCode::PRECONDITION_FAILED),
+        };
+
+        if vary {
+            Some(response.with_header("Vary", "Accept-Encoding"))
+        } else {
+            Some(response)
+        }
+    }
+
+    fn serve_file(&self, req: &Request, path: &Path, mime: &str, len: u64, etag: &str, last_modified: Option<DateTime>)
+                  -> Result<Response, Error>
+    {
+        // A range is only honoured if the client's copy is still current, otherwise they get the whole file.
+        let range = req.header().get_first("Range").filter(|_| {
+            match req.header().get_first("If-Range") {
+                Some(validator) => if_range_matches(validator, etag, last_modified),
+                None => true,
             }
+        });
 
-            _ => None,
+        let response = match range.map(|r| parse_range(r, len)) {
+            Some(Ranges::Satisfiable(ranges)) => partial_response(path, mime, &ranges, len)?,
+
+            Some(Ranges::Unsatisfiable) => unsatisfiable_response(len),
+
+            Some(Ranges::Invalid) | None => Response::from_file(StatusCode::OK, Some(mime), path)?,
         };
+
+        Ok(WebServer::with_validators(response, etag, last_modified).with_header("Accept-Ranges", "bytes"))
+    }
+
+    fn with_validators(response: Response, etag: &str, last_modified: Option<DateTime>) -> Response {
+        let response = response.with_header("ETag", etag);
+        match last_modified {
+            Some(date) => response.with_header("Last-Modified", format_http_date(&date)),
+            None => response,
+        }
     }
 
     fn find_requested_path(&self, url: &URL) -> Option<PathBuf> {
@@ -155,55 +537,194 @@ impl WebServer {
         return Some(canonicalised);
     }
 
+    fn respond(&self, req: Request) -> Response {
+        self.respond_with(req, Bindings::new())
+    }
+
+    fn respond_with(&self, req: Request, outer: Bindings) -> Response {
+        if let Some((handler, found)) = self.endpoints.find_match(req.method(), req.url()) {
+            let mut bindings = outer;
+            bindings.extend(found);
+            return handler.response(req, bindings);
+        }
+
+        // A file is served even where endpoints exist for other methods on the same path.
+        let (method, url) = (req.method(), req.url().clone());
+        if let Some(res) = self.handle_file_request(req) {
+            return res;
+        }
+
+        let mut allowed = self.endpoints.allowed_methods(&url);
+        if !allowed.is_empty() {
+            if self.find_requested_path(&url).is_some() {
+                allowed.extend([Method::GET, Method::HEAD]);
+                allowed.sort();
+                allowed.dedup();
+            }
+
+            let allow = allowed.iter().map(|m| m.as_str()).collect::<Vec<_>>().join(", ");
+            return match method {
+                Method::OPTIONS => Response::new(StatusCode::NO_CONTENT).without_header("Content-Length"),
+                _ => Response::new(StatusCode::METHOD_NOT_ALLOWED),
+            }.with_header("Allow", allow);
+        }
+
+        WebServer::not_found_response()
+    }
+
+    fn record(&self, entry: Option<AccessLogEntry>, status: StatusCode, bytes: Option<u64>, started: Instant) {
+        if let (Some(access_log), Some(entry)) = (&self.access_log, entry) {
+            access_log.record(&AccessLogEntry { status, bytes, duration: started.elapsed(), ..entry });
+        }
+    }
+
+    fn error_response(code: StatusCode) -> Response {
+        Response::new(code)
+            .with_header("Connection", "close")
+    }
+
     fn not_found_response() -> Response {
-            Response::from_text(404, "text/html", "<html><body><h1>Not Found</h1></body></html>")
+            Response::from_text(StatusCode::NOT_FOUND, "text/html", "<html><body><h1>Not Found</h1></body></html>")
+    }
+}
+
+#[derive(Clone)]
+struct Mount {
+    depth: usize,
+    server: Arc<WebServer>,
+}
+
+impl Mount {
+    const TAIL: &'static str = "..";
+}
+
+impl EndpointResponder for Mount {
+    fn response(&self, req: Request, mut bindings: Bindings) -> Response {
+        bindings.remove(Mount::TAIL);
+
+        let mut url = req.url().clone().with_resource("");
+        for segment in &req.url().resource()[self.depth..] {
+            url.push(segment.as_str());
+        }
+
+        let req = req.with_url(url);
+        middleware::apply(&self.server.middleware, req, |req| self.server.respond_with(req, bindings))
     }
 }
 
 impl WebService for WebServer {
-    fn handle_connection(&self, con: impl Read + Write, client: SocketAddr) {
-        println!("Started serving client: {}", client);
-        let mut stream = Stream::new(con);
-        loop {
-            let req = match stream.recv() {
+    fn handle_connection(&self, con: impl Connection, client: SocketAddr) {
+        log::debug(format!("Started serving client: {}", client));
+        let mut stream = Stream::new(con)
+            .with_limits(self.limits)
+            .with_timeouts(self.timeouts);
+
+        for served in 1..=self.max_requests {
+            let mut req = match stream.recv() {
                 Ok(x) => x,
+                Err(Error::ConnectionClosed) => break,
                 Err(e) => {
-                    if let Error::ConnectionClosed = e {
-                        break;
+                    if let Some(code) = e.response_code() {
+                        let entry = self.access_log.as_ref().map(|_| AccessLogEntry::started(client, None));
+                        let sent = stream.send(WebServer::error_response(code));
+                        self.record(entry, code, sent.ok(), Instant::now());
                     } else {
-                        eprintln!("Error receiving request! Error: {:?}", e);
-                        break;
+                        log::warn(format!("Error receiving request from {}! Error: {:?}", client, e));
                     }
+                    break;
                 }
             };
 
-            let callback = self
-                .endpoints
-                .find_match(req.method(), req.url())
-                .map(|(h, b)| h.response(req.clone(), b));
+            let keep_alive = req.keep_alive();
+            let version = req.version();
+            let head = req.method() == Method::HEAD;
+            let entry = self.access_log.as_ref().map(|_| AccessLogEntry::started(client, Some(&req)));
+            let started = Instant::now();
 
-            let response = match callback {
-                Some(res) => res,
-                None => self.handle_file_request(req)
-                    .unwrap_or(WebServer::not_found_response()),
-            };
+            req.set_named_endpoints(self.endpoints.named().clone());
+            let response = middleware::apply(&self.middleware, req, |req| self.respond(req));
+
+            // Reply in the client's version, which may itself require the connection to be closed.
+            let response = response.with_version(version);
+
+            // Responses to HEAD are those to GET without the body, so the header still describes it. This comes after
+            // the version, which decides how that body would have been framed.
+            let response = if head { response.without_body() } else { response };
 
-            match stream.send(response) {
+            // Either side may ask for the connection to be closed after this exchange, as may our own limit.
+            let close = !keep_alive
+                || served == self.max_requests
+                || response.header().contains_token("Connection", "close");
+            let response = response
+                .without_header("Connection")
+                .with_header("Connection", if close { "close" } else { "keep-alive" });
+
+            let status = response.code();
+            let sent = stream.send(response);
+            self.record(entry, status, sent.as_ref().ok().copied(), started);
+
+            match sent {
                 Ok(_) => (),
                 Err(Error::ConnectionClosed) => {
                     break;
                 }
                 Err(e) => {
-                    eprintln!("Error sending response! Error: {:?}", e);
+                    log::warn(format!("Error sending response to {}! Error: {:?}", client, e));
                     break;
                 }
             }
+
+            if close {
+                break;
+            }
         }
 
-        println!("Stopped serving client: {}", client);
+        log::debug(format!("Stopped serving client: {}", client));
     }
 }
 
 pub trait FileResponder {
     fn response(&self, req: Request, file: PathBuf) -> Response;
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn precompressed_files() {
+        let root = std::env::temp_dir().join(format!("http-precompressed-{}", rand::random::<u64>()));
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("page.html"), "<p>uncompressed</p>").unwrap();
+        std::fs::write(root.join("page.html.gz"), "gzipped").unwrap();
+
+        let server = WebServer::new().with_root(&root);
+        let respond = |headers: &[(&str, &str)]| {
+            let req = headers.iter().fold(Request::new(Method::GET, URL::from_string("/page.html").unwrap()), |req, (k, v)| {
+                req.with_header(k, *v)
+            });
+            server.handle_file_request(req).unwrap()
+        };
+
+        let res = respond(&[("Accept-Encoding", "gzip, deflate")]);
+        assert_eq!(res.code(), StatusCode::OK);
+        assert_eq!(res.header().get_first("Content-Encoding"), Some("gzip"));
+        assert_eq!(res.header().get_first("Content-Type"), Some("text/html"));
+        assert_eq!(res.header().content_length(), Some(7));
+        assert_eq!(res.header().get_first("Vary"), Some("Accept-Encoding"));
+
+        let res = respond(&[]);
+        assert_eq!(res.header().get_first("Content-Encoding"), None);
+        assert_eq!(res.header().content_length(), Some(19));
+        assert_eq!(res.header().get_first("Vary"), Some("Accept-Encoding"));
+
+        // Ranges refer to the uncompressed file, so a ranged request is never answered from the gzipped copy.
+        let res = respond(&[("Accept-Encoding", "gzip"), ("Range", "bytes=3-14")]);
+        assert_eq!(res.code(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(res.header().get_first("Content-Encoding"), None);
+        assert_eq!(res.header().get_first("Content-Range"), Some("bytes 3-14/19"));
+        assert_eq!(res.header().get_first("Vary"), Some("Accept-Encoding"));
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
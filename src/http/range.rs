@@ -0,0 +1,255 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{Cursor, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use super::{Error, Response, StatusCode};
+
+// Beyond this many ranges the whole resource is sent instead.
+const MAX_RANGES: usize = 16;
+
+/// An inclusive range of byte offsets within a resource.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl ByteRange {
+    pub fn len(&self) -> u64 {
+        self.end - self.start + 1
+    }
+
+    pub fn content_range(&self, total: u64) -> String {
+        format!("bytes {}-{}/{}", self.start, self.end, total)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Ranges {
+    /// The header was malformed, used a unit other than bytes or asked for too many ranges, so it must be ignored.
+    Invalid,
+    Unsatisfiable,
+    /// The ranges to send, sorted and with overlapping or adjacent ranges merged.
+    Satisfiable(Vec<ByteRange>),
+}
+
+pub fn parse_range(value: &str, len: u64) -> Ranges {
+    let specs = match value.trim().split_once('=') {
+        Some((unit, specs)) if unit.trim().eq_ignore_ascii_case("bytes") => specs,
+        _ => return Ranges::Invalid,
+    };
+
+    let mut ranges = Vec::new();
+
+    for spec in specs.split(',').map(|s| s.trim()).filter(|s| !s.is_empty()) {
+        let (first, last) = match spec.split_once('-') {
+            Some(x) => x,
+            None => return Ranges::Invalid,
+        };
+
+        let range = match (first.trim(), last.trim()) {
+            ("", "") => return Ranges::Invalid,
+
+            ("", suffix) => match suffix.parse::<u64>() {
+                Ok(0) => None,
+                Ok(n) if len > 0 => Some(ByteRange { start: len.saturating_sub(n), end: len - 1 }),
+                Ok(_) => None,
+                Err(_) => return Ranges::Invalid,
+            },
+
+            (first, last) => {
+                let start: u64 = match first.parse() {
+                    Ok(s) => s,
+                    Err(_) => return Ranges::Invalid,
+                };
+
+                let end: u64 = match last {
+                    "" => u64::MAX,
+                    last => match last.parse() {
+                        Ok(e) if e >= start => e,
+                        _ => return Ranges::Invalid,
+                    },
+                };
+
+                if start < len {
+                    Some(ByteRange { start, end: end.min(len - 1) })
+                } else {
+                    None
+                }
+            }
+        };
+
+        ranges.extend(range);
+    }
+
+    if ranges.is_empty() {
+        return Ranges::Unsatisfiable;
+    }
+
+    ranges.sort_by_key(|r| r.start);
+    let mut merged: Vec<ByteRange> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end.saturating_add(1) => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+
+    if merged.len() > MAX_RANGES {
+        return Ranges::Invalid;
+    }
+
+    Ranges::Satisfiable(merged)
+}
+
+/// Builds a `206 Partial Content` response for `ranges` of the file at `path`, using `multipart/byteranges` when
+/// there is more than one range.
+pub fn partial_response(path: &Path, mime: &str, ranges: &[ByteRange], total: u64) -> Result<Response, Error> {
+    if let [range] = ranges {
+        let file = open_range(path, range)?;
+        return Ok(Response::new(StatusCode::PARTIAL_CONTENT)
+            .with_header("Content-Range", range.content_range(total))
+            .with_reader_body(mime, file, range.len()));
+    }
+
+    let boundary = format!("{:016x}", rand::random::<u64>());
+    let mut parts = VecDeque::new();
+    let mut len = 0;
+
+    for (i, range) in ranges.iter().enumerate() {
+        let head = format!(
+            "{}--{}\r\nContent-Type: {}\r\nContent-Range: {}\r\n\r\n",
+            if i == 0 { "" } else { "\r\n" }, boundary, mime, range.content_range(total)
+        );
+        len += head.len() as u64 + range.len();
+        parts.push_back(Part::Text(Cursor::new(head.into_bytes())));
+        parts.push_back(Part::Range(*range));
+    }
+
+    let tail = format!("\r\n--{}--\r\n", boundary);
+    len += tail.len() as u64;
+    parts.push_back(Part::Text(Cursor::new(tail.into_bytes())));
+
+    let file = File::open(path).map_err(Error::IOError)?;
+    let body = Multipart { file, parts };
+
+    Ok(Response::new(StatusCode::PARTIAL_CONTENT)
+        .with_reader_body(&format!("multipart/byteranges; boundary={}", boundary), body, len))
+}
+
+pub fn unsatisfiable_response(total: u64) -> Response {
+    Response::new(StatusCode::RANGE_NOT_SATISFIABLE)
+        .with_header("Content-Range", format!("bytes */{}", total))
+}
+
+fn open_range(path: &Path, range: &ByteRange) -> Result<impl Read + Send, Error> {
+    let mut file = File::open(path).map_err(Error::IOError)?;
+    file.seek(SeekFrom::Start(range.start)).map_err(Error::IOError)?;
+    Ok(file.take(range.len()))
+}
+
+struct Multipart {
+    file: File,
+    parts: VecDeque<Part>,
+}
+
+enum Part {
+    Text(Cursor<Vec<u8>>),
+    Range(ByteRange),
+}
+
+impl Read for Multipart {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while let Some(part) = self.parts.front_mut() {
+            let n = match part {
+                Part::Text(text) => text.read(buf)?,
+
+                Part::Range(range) if range.start > range.end => 0,
+                Part::Range(range) => {
+                    let wanted = range.len().min(buf.len() as u64) as usize;
+                    let n = self.file.read(&mut buf[..wanted])?;
+                    if n == 0 && wanted > 0 {
+                        return Err(std::io::ErrorKind::UnexpectedEof.into());
+                    }
+                    range.start += n as u64;
+                    n
+                }
+            };
+
+            if n > 0 || buf.is_empty() {
+                return Ok(n);
+            }
+
+            self.parts.pop_front();
+            if let Some(Part::Range(range)) = self.parts.front() {
+                self.file.seek(SeekFrom::Start(range.start))?;
+            }
+        }
+
+        Ok(0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn range(start: u64, end: u64) -> ByteRange {
+        ByteRange { start, end }
+    }
+
+    #[test]
+    fn single_ranges() {
+        assert_eq!(parse_range("bytes=0-499", 1000), Ranges::Satisfiable(vec![range(0, 499)]));
+        assert_eq!(parse_range("bytes=500-", 1000), Ranges::Satisfiable(vec![range(500, 999)]));
+        assert_eq!(parse_range("bytes=-200", 1000), Ranges::Satisfiable(vec![range(800, 999)]));
+        assert_eq!(parse_range("bytes=900-5000", 1000), Ranges::Satisfiable(vec![range(900, 999)]));
+        assert_eq!(parse_range("bytes=-5000", 1000), Ranges::Satisfiable(vec![range(0, 999)]));
+    }
+
+    #[test]
+    fn multiple_ranges() {
+        assert_eq!(parse_range("bytes=500-600, 0-99", 1000), Ranges::Satisfiable(vec![range(0, 99), range(500, 600)]));
+        assert_eq!(parse_range("bytes=0-99,50-199,200-299", 1000), Ranges::Satisfiable(vec![range(0, 299)]));
+
+        let many = (0..20).map(|i| format!("{}-{}", i * 10, i * 10 + 1)).collect::<Vec<_>>().join(",");
+        assert_eq!(parse_range(&format!("bytes={}", many), 1000), Ranges::Invalid);
+        let adjacent = (0..20).map(|i| format!("{}-{}", i * 10, i * 10 + 9)).collect::<Vec<_>>().join(",");
+        assert_eq!(parse_range(&format!("bytes={}", adjacent), 1000), Ranges::Satisfiable(vec![range(0, 199)]));
+    }
+
+    #[test]
+    fn multipart_body() {
+        let path = std::env::temp_dir().join(format!("http-ranges-{}", rand::random::<u64>()));
+        std::fs::write(&path, "0123456789abcdefghij").unwrap();
+
+        let response = partial_response(&path, "text/plain", &[range(2, 4), range(15, 19)], 20).unwrap();
+        let boundary = response.header().content_type().unwrap().split_once("boundary=").unwrap().1.to_string();
+        let len = response.header().content_length().unwrap();
+
+        let mut body = Vec::new();
+        response.write_to(&mut body).unwrap();
+        let body = String::from_utf8(body).unwrap();
+        let body = body.split_once("\r\n\r\n").unwrap().1;
+
+        assert_eq!(body.len() as u64, len);
+        assert_eq!(body, format!(
+            "--{b}\r\nContent-Type: text/plain\r\nContent-Range: bytes 2-4/20\r\n\r\n234\r\n\
+             --{b}\r\nContent-Type: text/plain\r\nContent-Range: bytes 15-19/20\r\n\r\nfghij\r\n--{b}--\r\n",
+            b = boundary
+        ));
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn invalid_and_unsatisfiable() {
+        assert_eq!(parse_range("items=0-5", 1000), Ranges::Invalid);
+        assert_eq!(parse_range("bytes=5-1", 1000), Ranges::Invalid);
+        assert_eq!(parse_range("bytes=abc", 1000), Ranges::Invalid);
+        assert_eq!(parse_range("bytes=1000-", 1000), Ranges::Unsatisfiable);
+        assert_eq!(parse_range("bytes=-0", 1000), Ranges::Unsatisfiable);
+        assert_eq!(parse_range("bytes=-10", 0), Ranges::Unsatisfiable);
+    }
+}
@@ -1,6 +1,8 @@
 use std::convert::TryFrom;
-use std::io::{BufRead, BufReader, Read};
+use std::borrow::Borrow;
+use std::io::{BufRead, Write};
 use std::string::FromUtf8Error;
+use std::sync::Arc;
 
 use super::*;
 use crate::url::URL;
@@ -9,84 +11,159 @@ use crate::url::URL;
 pub struct Request {
     method: Method,
     url: URL,
+    version: Version,
     header: Header,
     body: Vec<u8>,
+    session: Option<Session>,
+    named: Option<Arc<NamedEndpoints>>,
 }
 
 impl Request {
-    pub fn read<F: Read>(stream: &mut F) -> Result<Self, Error> {
-        let mut reader = BufReader::new(stream);
-        let mut line = String::new();
-        let mut lines = Vec::new();
-
-        loop {
-            match reader.read_line(&mut line) {
-                Ok(n) if n > 0 => {
-                    if line == "\r\n" {
-                        break;
-                    } else {
-                        lines.push(line.clone());
-                        line.clear();
-                    }
-                }
-
-                Ok(_) => {
-                    return Err(Error::ConnectionClosed);
-                }
-
-                Err(e) => {
-                    return Err(Error::IOError(e));
-                }
-            }
+    /// Creates a request to send to `url`, for use with a `Client`.
+    pub fn new(method: Method, url: URL) -> Self {
+        Self {
+            method,
+            url,
+            version: Version::HTTP11,
+            header: Header::new(),
+            body: Vec::new(),
+            session: None,
+            named: None,
         }
+    }
 
-        let (method, url) = {
-            let top = lines.get(0).ok_or(Error::RequestParse)?;
-            let top: Vec<&str> = top.split(" ").collect();
-            let verb = Method::try_from(top[0]).map_err(|_| Error::RequestParse)?;
-            let resource = URL::from_string(top[1]).map_err(|_| Error::URLParse)?;
-            (verb, resource)
-        };
+    pub fn with_header(mut self, key: &str, value: impl Borrow<str>) -> Self {
+        self.header.add(key, value);
+        self
+    }
 
-        let mut header = Header::new();
-        for line in lines.drain(..).skip(1) {
-            let colon = line.find(":").unwrap();
-            let key = line[0..colon].to_owned();
-            let value = line[colon + 1..].trim().to_owned();
-            header.add(key, value);
-        }
+    pub fn without_header(mut self, key: &str) -> Self {
+        self.header.remove(key);
+        self
+    }
+
+    pub fn with_body(mut self, content_type: &str, body: Vec<u8>) -> Self {
+        self.header.replace("Content-Type", content_type);
+        self.header.replace("Content-Length", body.len().to_string());
+        Self { body, ..self }
+    }
 
-        let body = {
-            let content_length: usize = header
-                .get_first("Content-Length")
-                .unwrap_or(&"0".to_owned())
-                .parse()
-                .map_err(|_| Error::InvalidHeader)?;
-            let mut data = vec![0; content_length];
-            reader
-                .read_exact(&mut data[..])
-                .map_err(|e| Error::IOError(e))?;
-            data
+    pub fn with_url(self, url: URL) -> Self {
+        Self { url, ..self }
+    }
+
+    pub fn with_method(self, method: Method) -> Self {
+        Self { method, ..self }
+    }
+
+    pub fn read<F: BufRead>(reader: &mut F) -> Result<Self, Error> {
+        Self::read_with_limits(reader, &Limits::default())
+    }
+
+    pub fn read_with_limits<F: BufRead>(reader: &mut F, limits: &Limits) -> Result<Self, Error> {
+        let mut req = Self::read_head(reader, limits)?;
+        req.read_body(reader, limits)?;
+        Ok(req)
+    }
+
+    pub(super) fn read_head<F: BufRead>(reader: &mut F, limits: &Limits) -> Result<Self, Error> {
+        let mut line = String::new();
+        read_line(reader, &mut line, limits.max_request_line(), Error::URITooLong)?;
+
+        let (method, url, version) = {
+            let top: Vec<&str> = line.trim_end().split(' ').collect();
+            let (verb, resource, version) = match top[..] {
+                [verb, resource, version] => (verb, resource, version),
+                _ => return Err(Error::RequestParse),
+            };
+
+            let version = Version::try_from(version)?;
+            let verb = Method::try_from(verb).map_err(|_| Error::UnsupportedMethod)?;
+            let resource = URL::from_string(resource).map_err(|_| Error::URLParse)?;
+            (verb, resource, version)
         };
 
-        let req = Self {
+        let header = read_header(reader, limits)?;
+
+        Ok(Self {
             method,
             url,
+            version,
             header,
-            body,
-        };
+            body: Vec::new(),
+            session: None,
+            named: None,
+        })
+    }
 
-        return Ok(req);
+    pub(super) fn read_body<F: BufRead>(&mut self, reader: &mut F, limits: &Limits) -> Result<(), Error> {
+        self.body = read_body(reader, &mut self.header, limits)?;
+        Ok(())
     }
 
     pub fn method(&self) -> Method {
         self.method
     }
 
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    pub fn with_version(self, version: Version) -> Self {
+        Self { version, ..self }
+    }
+
+    /// The session of the client, when the `WebServer` serving the request has sessions enabled.
+    pub fn session(&self) -> Option<&Session> {
+        self.session.as_ref()
+    }
+
+    pub(crate) fn set_session(&mut self, session: Session) {
+        self.session = Some(session);
+    }
+
+    /// Builds the URL of a named endpoint of the `WebServer` serving the request, as `NamedEndpoints::url_for`
+    /// does. Fails with `Error::UnknownEndpoint` for requests not being served by a `WebServer`.
+    pub fn url_for(&self, name: &str, bindings: &Bindings) -> Result<URL, Error> {
+        self.named.as_ref().ok_or(Error::UnknownEndpoint)?.url_for(name, bindings)
+    }
+
+    pub(crate) fn set_named_endpoints(&mut self, named: Arc<NamedEndpoints>) {
+        self.named = Some(named);
+    }
+
+    pub(crate) fn header_mut(&mut self) -> &mut Header {
+        &mut self.header
+    }
+
+    pub(crate) fn clone_head(&self) -> Self {
+        Self {
+            method: self.method,
+            url: self.url.clone(),
+            version: self.version,
+            header: self.header.clone(),
+            body: Vec::new(),
+            session: self.session.clone(),
+            named: self.named.clone(),
+        }
+    }
+
     pub fn url(&self) -> &URL {
         &self.url
     }
 
+    /// Whether the client is willing to keep the connection open for further requests. HTTP/1.1 connections
+    /// persist unless the client sends `Connection: close`, HTTP/1.0 ones only if it sends `Connection: keep-alive`.
+    pub fn keep_alive(&self) -> bool {
+        if self.header.contains_token("Connection", "close") {
+            false
+        } else if self.version == Version::HTTP10 {
+            self.header.contains_token("Connection", "keep-alive")
+        } else {
+            true
+        }
+    }
+
     /// Returns the first header found with the given key.
     pub fn header(&self) -> &Header {
         &self.header
@@ -99,4 +176,20 @@ impl Request {
     pub fn body_as_string(&self) -> Result<String, FromUtf8Error> {
         String::from_utf8(self.body.clone())
     }
+
+    /// Serializes the request onto `writer` in origin-form, as sent to a server.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        let mut head = String::new();
+
+        head += &format!("{} {} {}\r\n", self.method.as_str(), self.url.target(), self.version.as_str());
+
+        for (key, value) in &self.header {
+            head += &format!("{}: {}\r\n", key, value);
+        }
+
+        head += "\r\n";
+
+        writer.write_all(head.as_bytes()).map_err(Error::IOError)?;
+        writer.write_all(&self.body).map_err(Error::IOError)
+    }
 }
@@ -1,30 +1,42 @@
+use super::{read_body, read_header, read_line};
+use super::Body;
 use super::Error;
 use super::Header;
+use super::Limits;
+use super::Method;
+use super::StatusCode;
+use super::Version;
+use crate::log;
 use crate::mime::extension_to_mime;
 use std::borrow::Borrow;
+use std::convert::TryFrom;
 use std::fs::File;
-use std::io::Read;
+use std::io::{BufRead, Read, Write};
 use std::path::Path;
-use crate::http::Cookie;
+use crate::http::{Cookie, CookieJar};
 
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub struct Response {
-    code: usize,
+    version: Version,
+    code: StatusCode,
+    reason: Option<String>,
     header: Header,
-    body: Vec<u8>,
+    body: Body,
 }
 
 impl Response {
-    pub fn new(code: usize) -> Self {
+    pub fn new(code: StatusCode) -> Self {
         Response {
+            version: Version::HTTP11,
             code: code,
+            reason: None,
             header: Header::new(),
-            body: Vec::new(),
+            body: Body::empty(),
         }.with_header("Content-Length", "0")
     }
 
     pub fn redirect<S: Borrow<str> + ?Sized>(resource: &S) -> Self {
-        Self::new(302).with_header("Location", resource.borrow())
+        Self::new(StatusCode::FOUND).with_header("Location", resource.borrow())
     }
 
     pub fn with_header(mut self, key: &str, value: impl Borrow<str>) -> Self {
@@ -32,25 +44,76 @@ impl Response {
         return self;
     }
 
+    pub fn try_with_header(mut self, key: &str, value: impl Borrow<str>) -> Result<Self, Error> {
+        self.header.try_add(key, value)?;
+        Ok(self)
+    }
+
+    pub fn without_header(mut self, key: &str) -> Self {
+        self.header.remove(key);
+        self
+    }
+
+    /// Adds a `Set-Cookie` header for `cookie`, or drops it with a warning if it is invalid.
     pub fn with_cookie(mut self, cookie: Cookie) -> Self {
-        self.header.add("Set-Cookie", cookie.to_string());
+        self.add_cookie(&cookie);
         return self;
     }
 
+    /// Adds a `Set-Cookie` header for every change made to `jar`.
+    pub fn with_cookies(mut self, jar: &CookieJar) -> Self {
+        for cookie in jar.delta() {
+            self.add_cookie(cookie);
+        }
+        self
+    }
+
+    fn add_cookie(&mut self, cookie: &Cookie) {
+        if cookie.is_valid() {
+            self.header.add("Set-Cookie", cookie.to_string());
+        } else {
+            log::warn(format!("Dropped invalid cookie {:?}={:?}", cookie.name(), cookie.value()));
+        }
+    }
+
     pub fn with_body(mut self, content_type: &str, body: Vec<u8>) -> Self {
         let len = body.len();
         self.header.replace("Content-Type", content_type);
         self.header.replace("Content-Length", len.to_string());
-        Self { body, ..self }
+        self.header.remove("Transfer-Encoding");
+        Self { body: Body::Bytes(body), ..self }
     }
 
-    pub fn from_text(code: usize, mime: &str, text: &str) -> Self {
+    /// Drops the body while keeping the header describing it, as is done when answering a `HEAD` request.
+    pub fn without_body(self) -> Self {
+        Self { body: Body::empty(), ..self }
+    }
+
+    /// Sets a body of unknown length which is streamed to the client using chunked encoding.
+    pub fn with_chunked_body(mut self, content_type: &str, body: impl Read + Send + 'static) -> Self {
+        self.header.replace("Content-Type", content_type);
+        self.header.remove("Content-Length");
+        self.header.replace("Transfer-Encoding", "chunked");
+        Self { body: Body::Chunked(Box::new(body)), ..self }
+    }
+
+    /// Sets a body of known length which is read from `body` while the response is being sent.
+    pub fn with_reader_body(mut self, content_type: &str, body: impl Read + Send + 'static, len: u64) -> Self {
+        self.header.replace("Content-Type", content_type);
+        self.header.replace("Content-Length", len.to_string());
+        self.header.remove("Transfer-Encoding");
+        Self { body: Body::Sized(Box::new(body), len), ..self }
+    }
+
+    pub fn from_text(code: StatusCode, mime: &str, text: &str) -> Self {
         Self::new(code)
             .with_body(mime, text.as_bytes().to_vec())
     }
 
-    pub fn from_file(code: usize, mime: Option<&str>, path: impl AsRef<Path>) -> Result<Self, Error> {
-        let mut file = File::open(path.as_ref()).map_err(|e| Error::IOError(e))?;
+    /// Creates a response which streams the file from disk rather than loading it into memory.
+    pub fn from_file(code: StatusCode, mime: Option<&str>, path: impl AsRef<Path>) -> Result<Self, Error> {
+        let file = File::open(path.as_ref()).map_err(Error::IOError)?;
+        let len = file.metadata().map_err(Error::IOError)?.len();
         let mime = mime.unwrap_or(
             extension_to_mime(
                 path.as_ref()
@@ -61,27 +124,230 @@ impl Response {
             ),
         );
 
-        let mut body = Vec::new();
-        file.read_to_end(&mut body).map_err(|e| Error::IOError(e))?;
-
         Ok(Response::new(code)
-            .with_body(mime, body))
+            .with_reader_body(mime, file, len))
+    }
+
+    /// Reads a response sent by a server, assuming it answers a request which may carry a body.
+    pub fn read<F: BufRead>(reader: &mut F) -> Result<Self, Error> {
+        Self::read_with_limits(reader, &Limits::default())
+    }
+
+    pub fn read_with_limits<F: BufRead>(reader: &mut F, limits: &Limits) -> Result<Self, Error> {
+        Self::read_for(reader, Method::GET, limits)
+    }
+
+    /// Reads the response to a request made with `method`, which decides whether a body follows the header.
+    /// A body delimited by the server closing the connection is read to the end and the response is marked
+    /// with `Connection: close`.
+    pub fn read_for<F: BufRead>(reader: &mut F, method: Method, limits: &Limits) -> Result<Self, Error> {
+        let mut line = String::new();
+        read_line(reader, &mut line, limits.max_request_line(), Error::ResponseParse)?;
+
+        let (version, code, reason) = {
+            let mut top = line.trim_end_matches(&['\r', '\n'][..]).splitn(3, ' ');
+
+            let version = match top.next() {
+                Some(version) => Version::try_from(version).map_err(|_| Error::ResponseParse)?,
+                None => return Err(Error::ResponseParse),
+            };
+
+            let code = top.next()
+                .filter(|c| c.len() == 3)
+                .and_then(|c| c.parse::<u16>().ok())
+                .and_then(StatusCode::custom)
+                .ok_or(Error::ResponseParse)?;
+
+            let reason = top.next()
+                .map(|r| r.trim().to_string())
+                .filter(|r| !r.is_empty());
+
+            (version, code, reason)
+        };
+
+        let mut header = read_header(reader, limits)?;
+
+        // Responses to HEAD, informational responses, 204 and 304 never have a body whatever their header says.
+        let body = if method == Method::HEAD || code.is_informational() || code == StatusCode::NO_CONTENT || code == StatusCode::NOT_MODIFIED {
+            Vec::new()
+        } else if header.get_first("Content-Length").is_none() && header.get_first("Transfer-Encoding").is_none() {
+            let mut data = Vec::new();
+            reader.take(limits.max_body_size() as u64 + 1).read_to_end(&mut data).map_err(Error::IOError)?;
+            if data.len() > limits.max_body_size() {
+                return Err(Error::PayloadTooLarge);
+            }
+
+            header.replace("Content-Length", data.len().to_string());
+            if !header.contains_token("Connection", "close") {
+                header.add("Connection", "close");
+            }
+            data
+        } else {
+            read_body(reader, &mut header, limits)?
+        };
+
+        Ok(Self {
+            version,
+            code,
+            reason,
+            header,
+            body: Body::Bytes(body),
+        })
+    }
+
+    /// Whether the server is willing to keep the connection open for further requests, following the same rules
+    /// as `Request::keep_alive`.
+    pub fn keep_alive(&self) -> bool {
+        if self.header.contains_token("Connection", "close") {
+            false
+        } else if self.version == Version::HTTP10 {
+            self.header.contains_token("Connection", "keep-alive")
+        } else {
+            true
+        }
+    }
+
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    /// Sets the version the response is sent in. An HTTP/1.0 client cannot decode chunked bodies, so those are
+    /// instead sent as they are and delimited by closing the connection.
+    pub fn with_version(mut self, version: Version) -> Self {
+        if version == Version::HTTP10 {
+            if let Body::Chunked(reader) = self.body {
+                self.header.remove("Transfer-Encoding");
+                self.header.replace("Connection", "close");
+                self.body = Body::Unsized(reader);
+            }
+        }
+
+        Self { version, ..self }
     }
 
-    pub fn as_bytes(&self) -> Vec<u8> {
-        let mut string = String::new();
+    pub fn code(&self) -> StatusCode {
+        self.code
+    }
+
+    /// The reason phrase sent after the status code, falling back to the canonical phrase for the code.
+    pub fn reason(&self) -> Option<&str> {
+        self.reason.as_deref().or_else(|| self.code.reason())
+    }
 
-        string += &format!("HTTP/1.1 {}\r\n", self.code.to_string());
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    pub fn body(&self) -> &Body {
+        &self.body
+    }
+
+    pub(crate) fn replace_body(&mut self, body: Body) -> Body {
+        std::mem::replace(&mut self.body, body)
+    }
+
+    /// Serializes the response onto `writer`, returning the length of the body sent.
+    pub fn write_to<W: Write>(self, writer: &mut W) -> Result<u64, Error> {
+        let mut head = String::new();
+
+        head += &format!("{} {} {}\r\n", self.version.as_str(), self.code, self.reason().unwrap_or(""));
 
         for (key, value) in &self.header {
-            string += &format!("{}: {}\r\n", key, value);
+            head += &format!("{}: {}\r\n", key, value);
         }
 
-        string += "\r\n";
+        head += "\r\n";
+
+        writer.write_all(head.as_bytes()).map_err(Error::IOError)?;
+        self.body.write_to(writer)
+    }
+}
+
+/// Anything an endpoint can return in place of a `Response`.
+pub trait IntoResponse {
+    fn into_response(self) -> Response;
+}
 
-        let mut bytes = string.as_bytes().to_owned();
-        bytes.append(&mut self.body.clone());
+impl IntoResponse for Response {
+    fn into_response(self) -> Response {
+        self
+    }
+}
 
-        return bytes;
+/// Lets endpoints use `?`, answering with whichever response the result holds.
+impl<T: IntoResponse, E: IntoResponse> IntoResponse for Result<T, E> {
+    fn into_response(self) -> Response {
+        match self {
+            Ok(response) => response.into_response(),
+            Err(response) => response.into_response(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(bytes: &[u8], method: Method) -> Response {
+        Response::read_for(&mut &bytes[..], method, &Limits::default()).unwrap()
+    }
+
+    fn body(response: &Response) -> &[u8] {
+        match response.body() {
+            Body::Bytes(bytes) => bytes,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut bytes = Vec::new();
+        Response::from_text(StatusCode::NOT_FOUND, "text/plain", "missing").write_to(&mut bytes).unwrap();
+
+        let response = parse(&bytes, Method::GET);
+        assert_eq!(response.code(), StatusCode::NOT_FOUND);
+        assert_eq!(response.reason(), Some("Not Found"));
+        assert_eq!(response.header().get_first("Content-Type"), Some("text/plain"));
+        assert_eq!(body(&response), b"missing");
+    }
+
+    #[test]
+    fn framing() {
+        let response = parse(b"HTTP/1.1 200 Very OK\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n0\r\n\r\n", Method::GET);
+        assert_eq!(response.reason(), Some("Very OK"));
+        assert_eq!(body(&response), b"abc");
+        assert!(response.keep_alive());
+
+        let response = parse(b"HTTP/1.0 200 OK\r\n\r\nuntil close", Method::GET);
+        assert_eq!(body(&response), b"until close");
+        assert!(!response.keep_alive());
+    }
+
+    #[test]
+    fn downgrade_chunked() {
+        let mut bytes = Vec::new();
+        Response::new(StatusCode::OK)
+            .with_chunked_body("text/plain", &b"streamed"[..])
+            .with_version(Version::HTTP10)
+            .write_to(&mut bytes)
+            .unwrap();
+
+        let response = parse(&bytes, Method::GET);
+        assert_eq!(response.version(), Version::HTTP10);
+        assert!(response.header().get_first("Transfer-Encoding").is_none());
+        assert_eq!(body(&response), b"streamed");
+    }
+
+    #[test]
+    fn no_body() {
+        let head = parse(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n", Method::HEAD);
+        assert!(body(&head).is_empty());
+        assert_eq!(head.header().get_first("Content-Length"), Some("10"));
+
+        for status in &["100 Continue", "204 No Content", "304 Not Modified"] {
+            let response = parse(format!("HTTP/1.1 {}\r\n\r\n", status).as_bytes(), Method::GET);
+            assert!(body(&response).is_empty());
+            assert!(response.keep_alive());
+        }
     }
 }
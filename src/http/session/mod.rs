@@ -0,0 +1,253 @@
+mod store;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use super::{Cookie, Middleware, Request, Response, SameSite};
+
+pub use store::{FileStore, MemoryStore, SessionStore};
+
+pub type SessionData = HashMap<String, String>;
+
+/// Middleware which loads a session for each request from a `SessionStore` using a cookie holding its id, and
+/// saves it again along with the cookie once the response is ready. The session is available to endpoints through
+/// `Request::session`. Cookies are `Secure`, `HttpOnly` and `SameSite=Lax` by default.
+pub struct Sessions {
+    store: Box<dyn SessionStore + Send + Sync>,
+    cookie_name: String,
+    path: String,
+    ttl: Duration,
+    secure: bool,
+    same_site: SameSite,
+}
+
+impl Sessions {
+    pub fn new(store: impl SessionStore + Send + Sync + 'static) -> Self {
+        Self {
+            store: Box::new(store),
+            cookie_name: "session".to_string(),
+            path: "/".to_string(),
+            ttl: Duration::from_secs(24 * 60 * 60),
+            secure: true,
+            same_site: SameSite::Lax,
+        }
+    }
+
+    pub fn with_cookie_name(self, cookie_name: &str) -> Self {
+        Self { cookie_name: cookie_name.to_string(), ..self }
+    }
+
+    pub fn with_path(self, path: &str) -> Self {
+        Self { path: path.to_string(), ..self }
+    }
+
+    /// How long a session lives after it was last changed.
+    pub fn with_ttl(self, ttl: Duration) -> Self {
+        Self { ttl, ..self }
+    }
+
+    /// Whether the cookie is only sent over HTTPS. Only turn this off for local development.
+    pub fn with_secure(self, secure: bool) -> Self {
+        Self { secure, ..self }
+    }
+
+    pub fn with_same_site(self, same_site: SameSite) -> Self {
+        Self { same_site, ..self }
+    }
+
+    pub fn load(&self, req: &Request) -> Session {
+        let existing = req.header()
+            .cookie(self.cookie_name.as_str())
+            .filter(|id| is_session_id(id))
+            .and_then(|id| self.store.load(id).map(|data| (id.to_string(), data)));
+
+        let state = match existing {
+            Some((id, data)) => State { id, data, original: None, status: Status::Unchanged, new: false },
+            None => State { id: generate_id(), data: SessionData::new(), original: None, status: Status::Unchanged, new: true },
+        };
+
+        Session { state: Arc::new(Mutex::new(state)) }
+    }
+
+    /// Saves any changes made to `session` while handling a request, and adds the cookie it needs to `response`.
+    pub fn save(&self, session: &Session, response: Response) -> Response {
+        let state = session.lock();
+
+        if let Some(original) = &state.original {
+            self.store.destroy(original);
+        }
+
+        match state.status {
+            Status::Destroyed => {
+                self.store.destroy(&state.id);
+                if state.new && state.original.is_none() {
+                    response
+                } else {
+                    response.with_cookie(self.cookie("").with_max_age(0))
+                }
+            }
+
+            // New sessions are only stored once something is put in them, so visitors who never log in or
+            // otherwise need a session are not given one.
+            Status::Modified if !(state.new && state.data.is_empty()) => {
+                self.store.store(&state.id, &state.data, self.ttl);
+                response.with_cookie(self.cookie(&state.id).with_max_age(self.ttl.as_secs() as i64))
+            }
+
+            _ => response,
+        }
+    }
+
+    fn cookie(&self, value: &str) -> Cookie {
+        Cookie::new(&self.cookie_name, value)
+            .with_path(&self.path)
+            .with_http_only(true)
+            .with_secure(self.secure)
+            .with_same_site(self.same_site)
+    }
+}
+
+impl Middleware for Sessions {
+    fn before(&self, req: &mut Request) -> Option<Response> {
+        let session = self.load(req);
+        req.set_session(session);
+        None
+    }
+
+    fn after(&self, req: &Request, res: Response) -> Response {
+        match req.session() {
+            Some(session) => self.save(session, res),
+            None => res,
+        }
+    }
+}
+
+/// A handle to the session of the request being handled.
+#[derive(Debug, Clone)]
+pub struct Session {
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Debug)]
+struct State {
+    id: String,
+    data: SessionData,
+    original: Option<String>,
+    status: Status,
+    new: bool,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Status {
+    Unchanged,
+    Modified,
+    Destroyed,
+}
+
+impl Session {
+    pub fn id(&self) -> String {
+        self.lock().id.clone()
+    }
+
+    pub fn is_new(&self) -> bool {
+        self.lock().new
+    }
+
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.lock().data.get(key).cloned()
+    }
+
+    pub fn insert(&self, key: &str, value: &str) {
+        let mut state = self.lock();
+        state.data.insert(key.to_string(), value.to_string());
+        state.status = Status::Modified;
+    }
+
+    pub fn remove(&self, key: &str) -> Option<String> {
+        let mut state = self.lock();
+        state.status = Status::Modified;
+        state.data.remove(key)
+    }
+
+    /// Moves the session to a new id, keeping its data. Call this when a user logs in so that an id planted by an
+    /// attacker before login cannot be used to hijack the session afterwards.
+    pub fn regenerate(&self) {
+        let mut state = self.lock();
+        if !state.new && state.original.is_none() {
+            state.original = Some(state.id.clone());
+        }
+
+        state.id = generate_id();
+        state.status = Status::Modified;
+    }
+
+    /// Deletes the session from the store and expires its cookie, such as when a user logs out.
+    pub fn destroy(&self) {
+        let mut state = self.lock();
+        state.data.clear();
+        state.status = Status::Destroyed;
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+}
+
+fn generate_id() -> String {
+    rand::random::<[u8; 32]>().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// Ids come from untrusted cookies and are used as file names by `FileStore`, so anything else is ignored.
+fn is_session_id(id: &str) -> bool {
+    id.len() == 64 && id.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::http::{Method, StatusCode};
+    use crate::url::URL;
+
+    fn request(cookie: Option<&str>) -> Request {
+        let req = Request::new(Method::GET, URL::from_string("/").unwrap());
+        match cookie {
+            Some(c) => req.with_header("Cookie", c),
+            None => req,
+        }
+    }
+
+    fn set_cookie(response: &Response) -> Option<Cookie> {
+        response.header().get_first("Set-Cookie").map(|c| Cookie::parse(c).unwrap())
+    }
+
+    #[test]
+    fn lifecycle() {
+        let sessions = Sessions::new(MemoryStore::new());
+
+        let session = sessions.load(&request(None));
+        assert!(set_cookie(&sessions.save(&session, Response::new(StatusCode::OK))).is_none());
+
+        session.insert("user", "alice");
+        let cookie = set_cookie(&sessions.save(&session, Response::new(StatusCode::OK))).unwrap();
+        assert!(cookie.secure() && cookie.http_only());
+        assert_eq!(cookie.same_site(), Some(SameSite::Lax));
+
+        let header = format!("session={}", cookie.value());
+        let session = sessions.load(&request(Some(&header)));
+        assert_eq!(session.get("user").as_deref(), Some("alice"));
+
+        session.regenerate();
+        let regenerated = set_cookie(&sessions.save(&session, Response::new(StatusCode::OK))).unwrap();
+        assert_ne!(regenerated.value(), cookie.value());
+        assert!(sessions.load(&request(Some(&header))).is_new());
+
+        let header = format!("session={}", regenerated.value());
+        let session = sessions.load(&request(Some(&header)));
+        assert_eq!(session.get("user").as_deref(), Some("alice"));
+
+        session.destroy();
+        assert!(set_cookie(&sessions.save(&session, Response::new(StatusCode::OK))).unwrap().is_removal());
+        assert!(sessions.load(&request(Some(&header))).is_new());
+    }
+}
@@ -0,0 +1,254 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use super::{is_session_id, SessionData};
+use crate::log;
+use crate::url::{decode, encode};
+
+/// Somewhere sessions are kept between requests. Ids passed to a store have already been checked to be 64 hex
+/// digits, so they are safe to use as keys or file names.
+pub trait SessionStore {
+    fn load(&self, id: &str) -> Option<SessionData>;
+
+    fn store(&self, id: &str, data: &SessionData, ttl: Duration);
+
+    fn destroy(&self, id: &str);
+}
+
+/// Keeps sessions in memory, so they are lost when the server restarts.
+pub struct MemoryStore {
+    sessions: Mutex<HashMap<String, (SessionData, Instant)>>,
+    purge_interval: Duration,
+    last_purge: Mutex<Instant>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self {
+            sessions: Mutex::new(HashMap::new()),
+            purge_interval: Duration::from_secs(60),
+            last_purge: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// How often expired sessions which were never loaded again are removed, checked whenever a session is stored.
+    pub fn with_purge_interval(self, purge_interval: Duration) -> Self {
+        Self { purge_interval, ..self }
+    }
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SessionStore for MemoryStore {
+    fn load(&self, id: &str) -> Option<SessionData> {
+        let mut sessions = self.sessions.lock().unwrap();
+        match sessions.get(id) {
+            Some((data, expiry)) if *expiry > Instant::now() => Some(data.clone()),
+            Some(_) => {
+                sessions.remove(id);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn store(&self, id: &str, data: &SessionData, ttl: Duration) {
+        let now = Instant::now();
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.insert(id.to_string(), (data.clone(), now + ttl));
+
+        if due(&self.last_purge, self.purge_interval) {
+            sessions.retain(|_, (_, expiry)| *expiry > now);
+        }
+    }
+
+    fn destroy(&self, id: &str) {
+        self.sessions.lock().unwrap().remove(id);
+    }
+}
+
+/// Keeps each session in a file in a directory, so sessions survive restarts. Each file holds the expiry time
+/// as seconds since the epoch on its first line, followed by a line of `key=value` for each entry, percent-encoded.
+pub struct FileStore {
+    directory: PathBuf,
+    purge_interval: Duration,
+    last_purge: Mutex<Instant>,
+}
+
+impl FileStore {
+    pub fn new(directory: impl AsRef<Path>) -> std::io::Result<Self> {
+        fs::create_dir_all(directory.as_ref())?;
+        Ok(Self {
+            directory: directory.as_ref().to_path_buf(),
+            purge_interval: Duration::from_secs(600),
+            last_purge: Mutex::new(Instant::now()),
+        })
+    }
+
+    pub fn with_purge_interval(self, purge_interval: Duration) -> Self {
+        Self { purge_interval, ..self }
+    }
+
+    fn path(&self, id: &str) -> PathBuf {
+        self.directory.join(id)
+    }
+
+    fn purge(&self) {
+        let entries = match fs::read_dir(&self.directory) {
+            Ok(entries) => entries,
+            Err(e) => {
+                log::warn(format!("Failed to purge expired sessions! Error: {}", e));
+                return;
+            }
+        };
+
+        let now = now();
+        for entry in entries.filter_map(Result::ok) {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+
+            let expired = if is_session_id(&name) {
+                read_expiry(&entry.path()).map(|expiry| expiry <= now).unwrap_or(false)
+            } else if name.ends_with(".tmp") {
+                let modified = entry.metadata().and_then(|m| m.modified());
+                modified.map(|m| m.elapsed().unwrap_or_default() > self.purge_interval).unwrap_or(false)
+            } else {
+                false
+            };
+
+            if expired {
+                let _ = fs::remove_file(entry.path());
+            }
+        }
+    }
+}
+
+impl SessionStore for FileStore {
+    fn load(&self, id: &str) -> Option<SessionData> {
+        let contents = fs::read_to_string(self.path(id)).ok()?;
+        let mut lines = contents.lines();
+
+        let expiry = parse_expiry(lines.next()?)?;
+        if expiry <= now() {
+            self.destroy(id);
+            return None;
+        }
+
+        lines
+            .map(|line| {
+                let (key, value) = line.split_once('=')?;
+                Some((decode(key).ok()?, decode(value).ok()?))
+            })
+            .collect()
+    }
+
+    fn store(&self, id: &str, data: &SessionData, ttl: Duration) {
+        let mut contents = format!("{}\n", now() + ttl.as_secs());
+        for (key, value) in data {
+            contents += &format!("{}={}\n", encode(key), encode(value));
+        }
+
+        // Write to a temporary file first so that a concurrent load never sees a half written session. Its name is
+        // unique so that concurrent stores of the same session do not write over each other's.
+        let temporary = self.directory.join(format!("{}.{:016x}.tmp", id, rand::random::<u64>()));
+        if let Err(e) = fs::write(&temporary, contents).and_then(|_| fs::rename(&temporary, self.path(id))) {
+            log::error(format!("Failed to store session! Error: {}", e));
+            let _ = fs::remove_file(&temporary);
+        }
+
+        if due(&self.last_purge, self.purge_interval) {
+            self.purge();
+        }
+    }
+
+    fn destroy(&self, id: &str) {
+        let _ = fs::remove_file(self.path(id));
+    }
+}
+
+fn due(last_purge: &Mutex<Instant>, interval: Duration) -> bool {
+    let mut last_purge = last_purge.lock().unwrap();
+    let due = last_purge.elapsed() >= interval;
+    if due {
+        *last_purge = Instant::now();
+    }
+    due
+}
+
+fn read_expiry(path: &Path) -> Option<u64> {
+    let file = fs::File::open(path).ok()?;
+    let mut line = String::new();
+    BufReader::new(file).read_line(&mut line).ok()?;
+    parse_expiry(line.trim_end())
+}
+
+fn parse_expiry(line: &str) -> Option<u64> {
+    line.parse().ok()
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn memory_store() {
+        let store = MemoryStore::new().with_purge_interval(Duration::from_secs(0));
+        let (expired, current) = ("a".repeat(64), "b".repeat(64));
+
+        store.store(&expired, &SessionData::new(), Duration::from_secs(0));
+        assert_eq!(store.load(&expired), None);
+
+        store.store(&expired, &SessionData::new(), Duration::from_secs(0));
+        store.store(&current, &SessionData::new(), Duration::from_secs(60));
+        assert_eq!(store.sessions.lock().unwrap().len(), 1);
+        assert_eq!(store.load(&current), Some(SessionData::new()));
+    }
+
+    #[test]
+    fn file_store() {
+        let directory = std::env::temp_dir().join(format!("http-sessions-{}", rand::random::<u64>()));
+        let store = FileStore::new(&directory).unwrap();
+        let id = "a".repeat(64);
+
+        let mut data = SessionData::new();
+        data.insert("user".to_string(), "alice & bob=friends\n".to_string());
+        store.store(&id, &data, Duration::from_secs(60));
+        assert_eq!(store.load(&id), Some(data.clone()));
+
+        store.store(&id, &data, Duration::from_secs(0));
+        assert_eq!(store.load(&id), None);
+        assert!(!directory.join(&id).exists());
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn purges_expired_files() {
+        let directory = std::env::temp_dir().join(format!("http-sessions-{}", rand::random::<u64>()));
+        let (expired, current) = ("a".repeat(64), "b".repeat(64));
+        FileStore::new(&directory).unwrap().store(&expired, &SessionData::new(), Duration::from_secs(0));
+        fs::write(directory.join("unrelated"), "1\n").unwrap();
+        assert!(directory.join(&expired).exists());
+
+        let store = FileStore::new(&directory).unwrap().with_purge_interval(Duration::from_secs(0));
+        store.store(&current, &SessionData::new(), Duration::from_secs(60));
+
+        let mut files: Vec<_> = fs::read_dir(&directory).unwrap().map(|e| e.unwrap().file_name()).collect();
+        files.sort();
+        assert_eq!(files, vec![current.into(), std::ffi::OsString::from("unrelated")]);
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
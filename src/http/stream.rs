@@ -1,33 +1,107 @@
-use std::io::{Read, Write};
+use std::io::{BufRead, BufReader, ErrorKind, Read};
+use std::time::{Duration, Instant};
 
-use crate::http::{Error, Request, Response};
+use crate::http::{Error, Limits, Request, Response, Timeouts};
+use crate::server::Connection;
 
 pub struct Stream<S>
 where
-    S: Read + Write,
+    S: Connection,
 {
-    connection: S,
+    connection: BufReader<Deadline<S>>,
+    limits: Limits,
+    timeouts: Timeouts,
 }
 
-impl<S: Read + Write> Stream<S> {
+impl<S: Connection> Stream<S> {
     pub fn new(connection: S) -> Self {
-        Self { connection }
+        Self {
+            connection: BufReader::new(Deadline { inner: connection, deadline: None }),
+            limits: Limits::new(),
+            timeouts: Timeouts::new(),
+        }
     }
 
+    pub fn with_limits(self, limits: Limits) -> Self {
+        Self { limits, ..self }
+    }
+
+    pub fn with_timeouts(self, timeouts: Timeouts) -> Self {
+        Self { timeouts, ..self }
+    }
+
+    /// Waits for the next request. A connection which stays idle past the idle timeout is reported as closed,
+    /// while a client that stalls part way through a request gets `Error::Timeout`.
     pub fn recv(&mut self) -> Result<Request, Error> {
-        Request::read(&mut self.connection)
+        if self.connection.buffer().is_empty() {
+            self.set_deadline(self.timeouts.idle());
+            match self.connection.fill_buf() {
+                Ok(_) => (),
+                Err(e) if is_timeout(&e) => return Err(Error::ConnectionClosed),
+                Err(e) => return Err(Error::IOError(e)),
+            }
+        }
+
+        self.set_deadline(self.timeouts.header());
+        let mut request = Request::read_head(&mut self.connection, &self.limits).map_err(timed_out)?;
+
+        self.set_deadline(self.timeouts.body());
+        request.read_body(&mut self.connection, &self.limits).map_err(timed_out)?;
+
+        self.set_deadline(None);
+        Ok(request)
+    }
+
+    pub fn send(&mut self, response: Response) -> Result<u64, Error> {
+        let connection = &mut self.connection.get_mut().inner;
+        connection.set_write_timeout(self.timeouts.write()).map_err(Error::IOError)?;
+        let sent = response.write_to(connection).map_err(timed_out)?;
+        connection.flush().map_err(Error::IOError)?;
+        Ok(sent)
+    }
+
+    /// Returns the underlying connection with its timeouts cleared, for protocols that take over from HTTP, along
+    /// with any bytes the client sent after the last request which were read from it but not yet consumed.
+    pub fn into_inner(self) -> (S, Vec<u8>) {
+        let buffered = self.connection.buffer().to_vec();
+        let connection = self.connection.into_inner().inner;
+        let _ = connection.set_read_timeout(None);
+        let _ = connection.set_write_timeout(None);
+        (connection, buffered)
+    }
+
+    fn set_deadline(&mut self, timeout: Option<Duration>) {
+        self.connection.get_mut().deadline = timeout.map(|t| Instant::now() + t);
     }
+}
+
+struct Deadline<S> {
+    inner: S,
+    deadline: Option<Instant>,
+}
+
+impl<S: Connection> Read for Deadline<S> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let remaining = match self.deadline {
+            Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                Some(remaining) if !remaining.is_zero() => Some(remaining),
+                _ => return Err(ErrorKind::TimedOut.into()),
+            },
+            None => None,
+        };
 
-    pub fn send(&mut self, response: Response) -> Result<(), Error> {
-        let bytes = response.as_bytes();
-        self.connection
-            .write_all(&bytes)
-            .map_err(|e| Error::IOError(e))?;
-        self.connection.flush().map_err(|e| Error::IOError(e))?;
-        Ok(())
+        self.inner.set_read_timeout(remaining)?;
+        self.inner.read(buf)
     }
+}
+
+fn is_timeout(e: &std::io::Error) -> bool {
+    matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)
+}
 
-    pub fn into_inner(self) -> S {
-        self.connection
+fn timed_out(e: Error) -> Error {
+    match e {
+        Error::IOError(e) if is_timeout(&e) => Error::Timeout,
+        e => e,
     }
 }
@@ -0,0 +1,62 @@
+use std::time::Duration;
+
+/// How long a `Stream` waits on a client at each stage of an exchange. `None` waits forever.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Timeouts {
+    header: Option<Duration>,
+    body: Option<Duration>,
+    write: Option<Duration>,
+    idle: Option<Duration>,
+}
+
+impl Timeouts {
+    pub fn new() -> Self {
+        Self {
+            header: Some(Duration::from_secs(20)),
+            body: Some(Duration::from_secs(60)),
+            write: Some(Duration::from_secs(60)),
+            idle: Some(Duration::from_secs(15)),
+        }
+    }
+
+    /// The total time allowed to receive the request line and headers once the first byte has arrived.
+    pub fn with_header(self, header: Option<Duration>) -> Self {
+        Self { header, ..self }
+    }
+
+    pub fn with_body(self, body: Option<Duration>) -> Self {
+        Self { body, ..self }
+    }
+
+    /// The time allowed for each write of the response to make progress.
+    pub fn with_write(self, write: Option<Duration>) -> Self {
+        Self { write, ..self }
+    }
+
+    /// How long a keep-alive connection may wait for its next request before it is closed.
+    pub fn with_idle(self, idle: Option<Duration>) -> Self {
+        Self { idle, ..self }
+    }
+
+    pub fn header(&self) -> Option<Duration> {
+        self.header
+    }
+
+    pub fn body(&self) -> Option<Duration> {
+        self.body
+    }
+
+    pub fn write(&self) -> Option<Duration> {
+        self.write
+    }
+
+    pub fn idle(&self) -> Option<Duration> {
+        self.idle
+    }
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -4,7 +4,9 @@ extern crate core;
 
 mod thread_pool;
 
+pub mod client;
 pub mod http;
+pub mod log;
 pub mod mime;
 pub mod server;
 pub mod url;
@@ -13,18 +15,20 @@ pub mod ws;
 #[cfg(test)]
 mod tests {
     use std::io::{Read, Write};
-    use crate::http::{Bindings, EndpointResponder, FileResponder, Request, Response, WebServer};
-    use crate::server::{Server, WebService};
+    use crate::http::{Bindings, EndpointResponder, FileResponder, Request, Response, StatusCode, WebServer};
+    use crate::server::{Connection, Server, WebService};
     use crate::ws::Message;
-    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+    use std::net::{IpAddr, Ipv4Addr, Shutdown, SocketAddr, TcpStream};
     use std::path::PathBuf;
-    use crate::http::Method::GET;
+    use std::thread::{sleep, spawn};
+    use std::time::{Duration, Instant};
+    use crate::http::Method::{GET, POST};
 
     struct Printer {}
 
     impl EndpointResponder for Printer {
         fn response(&self, _: Request, bindings: Bindings) -> Response {
-            Response::from_text(200, "text/plain", bindings.get("text").unwrap())
+            Response::from_text(StatusCode::OK, "text/plain", bindings.get("text").unwrap())
         }
     }
 
@@ -32,7 +36,7 @@ mod tests {
 
     impl EndpointResponder for ColorPrinter {
         fn response(&self, _: Request, bindings: Bindings) -> Response {
-            Response::from_text(200, "text/html",
+            Response::from_text(StatusCode::OK, "text/html",
                                 &format!("<html><body><h1 style=\"color:{}\">{}</h1></body></html>",
                                          bindings.get("color").unwrap(),
                                          bindings.get("text").unwrap(),
@@ -50,21 +54,177 @@ mod tests {
     }
 
 
+    fn request(port: u16, request: &str) -> String {
+        let mut con = TcpStream::connect((Ipv4Addr::LOCALHOST, port)).unwrap();
+        con.write_all(request.as_bytes()).unwrap();
+        con.shutdown(Shutdown::Write).unwrap();
+        let mut response = String::new();
+        con.read_to_string(&mut response).unwrap();
+        response
+    }
+
     #[test]
     fn webserver() {
         let service = WebServer::new()
             .with_root("./site")
-            .with_endpoint(GET, "/print/<text>", Printer {})
+            .with_named_endpoint("print", GET, "/print/<text>", Printer {})
+            .with_endpoint(GET, "/go/<text>", |req: Request, bindings: Bindings| {
+                Response::redirect(&req.url_for("print", &bindings).unwrap().target())
+            })
             .with_endpoint(GET, "/print/<color>/<text>", ColorPrinter {})
+            .with_mount("/mounted/<color>", WebServer::new().with_endpoint(GET, "/<text>", ColorPrinter {}))
+            .with_endpoint(POST, "/index.js", |_: Request, _: Bindings| Response::from_text(StatusCode::OK, "text/plain", "posted"))
+            .with_endpoint(GET, "/chunked", |_: Request, _: Bindings| {
+                Response::new(StatusCode::OK).with_chunked_body("text/plain", &b"chunks"[..])
+            })
             .with_file_mask("secure.html", SecurePage);
-        let mut server = Server::new(service);
-        server.run_secure();
+        let mut server = Server::new(service)
+            .with_port(8080)
+            .with_grace_period(Duration::from_secs(1));
+        let shutdown = server.shutdown_handle();
+        let server = spawn(move || server.run());
+        sleep(Duration::from_millis(200));
+
+        let response = request(8080, "GET /print/monkey HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.ends_with("monkey"));
+
+        let response = request(8080, "HEAD /print/monkey HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.contains("Content-Length: 6\r\n"));
+        assert!(response.ends_with("\r\n\r\n"));
+
+        let response = request(8080, "POST /print/monkey HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 405"));
+        assert!(response.contains("Allow: GET, HEAD, OPTIONS\r\n"));
+
+        let response = request(8080, "OPTIONS /print/red/monkey HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 204"));
+        assert!(response.contains("Allow: GET, HEAD, OPTIONS\r\n"));
+
+        let response = request(8080, "GET /mounted/red/monkey HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.contains("color:red\">monkey"));
+
+        let response = request(8080, "POST /mounted/red/monkey HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 405"));
+
+        let response = request(8080, "GET /go/funny%20monkey HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 302"));
+        assert!(response.contains("Location: /print/funny%20monkey\r\n"));
+
+        let response = request(8080, "GET /index.js HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(!response.ends_with("posted"));
+
+        let response = request(8080, "POST /index.js HTTP/1.1\r\n\r\n");
+        assert!(response.ends_with("posted"));
+
+        let response = request(8080, "PUT /index.js HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 405"));
+        assert!(response.contains("Allow: GET, HEAD, POST, OPTIONS\r\n"));
+
+        let response = request(8080, "HEAD /chunked HTTP/1.1\r\n\r\n");
+        assert!(response.contains("Transfer-Encoding: chunked\r\n"));
+        assert!(response.ends_with("\r\n\r\n"));
+
+        let response = request(8080, "HEAD /chunked HTTP/1.0\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.0 200"));
+        assert!(!response.contains("Transfer-Encoding"));
+        assert!(response.ends_with("\r\n\r\n"));
+
+        let response = request(8080, "GET /secure.html HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 302"));
+
+        shutdown.shutdown();
+        server.join().unwrap().unwrap();
+    }
+
+    struct SlowService;
+
+    impl WebService for SlowService {
+        fn handle_connection(&self, _: impl Connection, _: SocketAddr) {
+            sleep(Duration::from_secs(20));
+        }
+    }
+
+    #[test]
+    fn grace_period() {
+        let mut server = Server::new(SlowService)
+            .with_port(8083)
+            .with_grace_period(Duration::from_millis(200));
+        let shutdown = server.shutdown_handle();
+        let server = spawn(move || server.run());
+        sleep(Duration::from_millis(200));
+
+        let _con = TcpStream::connect((Ipv4Addr::LOCALHOST, 8083)).unwrap();
+        sleep(Duration::from_millis(100));
+
+        let started = Instant::now();
+        shutdown.shutdown();
+        server.join().unwrap().unwrap();
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    fn self_signed() -> (PathBuf, PathBuf) {
+        use openssl::asn1::Asn1Time;
+        use openssl::bn::BigNum;
+        use openssl::hash::MessageDigest;
+        use openssl::pkey::PKey;
+        use openssl::rsa::Rsa;
+        use openssl::x509::{X509, X509NameBuilder};
+
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("CN", "localhost").unwrap();
+        let name = name.build();
+
+        let mut cert = X509::builder().unwrap();
+        cert.set_version(2).unwrap();
+        cert.set_serial_number(&BigNum::from_u32(1).unwrap().to_asn1_integer().unwrap()).unwrap();
+        cert.set_subject_name(&name).unwrap();
+        cert.set_issuer_name(&name).unwrap();
+        cert.set_pubkey(&key).unwrap();
+        cert.set_not_before(&Asn1Time::days_from_now(0).unwrap()).unwrap();
+        cert.set_not_after(&Asn1Time::days_from_now(1).unwrap()).unwrap();
+        cert.sign(&key, MessageDigest::sha256()).unwrap();
+
+        let directory = std::env::temp_dir().join(format!("http-tls-{}", rand::random::<u64>()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let (cert_path, key_path) = (directory.join("cert.pem"), directory.join("key.pem"));
+        std::fs::write(&cert_path, cert.build().to_pem().unwrap()).unwrap();
+        std::fs::write(&key_path, key.private_key_to_pem_pkcs8().unwrap()).unwrap();
+        (cert_path, key_path)
+    }
+
+    #[test]
+    fn shutdown_with_idle_tls_client() {
+        let (cert, key) = self_signed();
+        let mut server = Server::new(WebServer::new())
+            .with_port(8085)
+            .with_certificate(Some(&cert))
+            .with_key(Some(&key))
+            .with_grace_period(Duration::from_millis(200));
+        let shutdown = server.shutdown_handle();
+        let server = spawn(move || server.run_secure());
+        sleep(Duration::from_millis(200));
+
+        // Never sends a ClientHello, so its handshake cannot complete.
+        let _idle = TcpStream::connect((Ipv4Addr::LOCALHOST, 8085)).unwrap();
+        sleep(Duration::from_millis(100));
+
+        let started = Instant::now();
+        shutdown.shutdown();
+        server.join().unwrap().unwrap();
+        assert!(started.elapsed() < Duration::from_secs(2));
+
+        std::fs::remove_dir_all(cert.parent().unwrap()).unwrap();
     }
 
     pub struct WebSocketService {}
 
     impl WebService for WebSocketService {
-        fn handle_connection(&self, con: impl Read + Write, client: SocketAddr) {
+        fn handle_connection(&self, con: impl Connection, client: SocketAddr) {
             use std::io::ErrorKind::ConnectionAborted;
             use crate::http::Stream as HTTPStream;
             use crate::ws::Error;
@@ -89,17 +249,47 @@ mod tests {
 
     #[test]
     fn websocket() {
-        use std::thread::spawn;
+        let mut wsserver = Server::new(WebSocketService {})
+            .with_socket(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 8081))
+            .with_grace_period(Duration::from_secs(1));
+        let shutdown = wsserver.shutdown_handle();
+        let wsserver = spawn(move || wsserver.run());
+        sleep(Duration::from_millis(200));
 
-        let mut httpserver = Server::new(WebServer::new());
+        let mut con = TcpStream::connect((Ipv4Addr::LOCALHOST, 8081)).unwrap();
+        con.write_all(b"GET / HTTP/1.1\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n").unwrap();
 
-        let mut wsserver = Server::new(WebSocketService {})
-            .with_socket(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 8081));
+        let mut handshake = Vec::new();
+        while !handshake.ends_with(b"\r\n\r\n") {
+            let mut byte = [0u8];
+            con.read_exact(&mut byte).unwrap();
+            handshake.push(byte[0]);
+        }
+        let handshake = String::from_utf8(handshake).unwrap();
+        assert!(handshake.starts_with("HTTP/1.1 101"));
+        assert!(handshake.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo="));
 
-        let httpserver = spawn(move || httpserver.run_secure());
-        let wsserver = spawn(move || wsserver.run_secure());
+        // A masked, single frame text message saying "Hi".
+        con.write_all(&[0x81, 0x82, 1, 2, 3, 4, b'H' ^ 1, b'i' ^ 2]).unwrap();
+        let mut frame = [0u8; 15];
+        con.read_exact(&mut frame).unwrap();
+        assert_eq!(&frame[2..], b"Funny Monkey!");
+
+        // A frame sent along with the handshake, before the server has answered it.
+        let mut eager = TcpStream::connect((Ipv4Addr::LOCALHOST, 8081)).unwrap();
+        eager.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        eager.write_all(b"GET / HTTP/1.1\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n\x81\x82\x01\x02\x03\x04\x49\x6b").unwrap();
+        let mut reply = Vec::new();
+        while !reply.ends_with(b"Funny Monkey!") {
+            let mut byte = [0u8];
+            eager.read_exact(&mut byte).unwrap();
+            reply.push(byte[0]);
+        }
+        assert!(reply.starts_with(b"HTTP/1.1 101"));
 
-        httpserver.join().unwrap();
-        wsserver.join().unwrap();
+        drop(con);
+        drop(eager);
+        shutdown.shutdown();
+        wsserver.join().unwrap().unwrap();
     }
 }
@@ -0,0 +1,240 @@
+use std::fmt::Display;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use chrono::Utc;
+
+use crate::http::{DateTime, Method, Request, StatusCode, Version};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl Level {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Error => "ERROR",
+            Self::Warn => "WARN",
+            Self::Info => "INFO",
+            Self::Debug => "DEBUG",
+        }
+    }
+}
+
+/// Receives the messages the server logs about its own operation, such as failed connections.
+pub trait Logger: Send + Sync {
+    fn log(&self, level: Level, message: &str);
+}
+
+/// The default logger, which prints messages at or above a level to stdout, and errors and warnings to stderr.
+pub struct ConsoleLogger {
+    level: Level,
+}
+
+impl ConsoleLogger {
+    pub fn new(level: Level) -> Self {
+        Self { level }
+    }
+}
+
+impl Logger for ConsoleLogger {
+    fn log(&self, level: Level, message: &str) {
+        match level {
+            _ if level > self.level => (),
+            Level::Error | Level::Warn => eprintln!("{}", message),
+            Level::Info | Level::Debug => println!("{}", message),
+        }
+    }
+}
+
+pub struct NullLogger;
+
+impl Logger for NullLogger {
+    fn log(&self, _level: Level, _message: &str) {}
+}
+
+static LOGGER: RwLock<Option<Arc<dyn Logger>>> = RwLock::new(None);
+
+/// Replaces the logger used by every server in the process. Until this is called a `ConsoleLogger` at
+/// `Level::Info` is used.
+pub fn set_logger(logger: impl Logger + 'static) {
+    *LOGGER.write().unwrap() = Some(Arc::new(logger));
+}
+
+pub fn log(level: Level, message: impl Display) {
+    let logger = LOGGER.read().unwrap().clone();
+    match logger {
+        Some(logger) => logger.log(level, &message.to_string()),
+        None => ConsoleLogger::new(Level::Info).log(level, &message.to_string()),
+    }
+}
+
+pub(crate) fn error(message: impl Display) {
+    log(Level::Error, message)
+}
+
+pub(crate) fn warn(message: impl Display) {
+    log(Level::Warn, message)
+}
+
+pub(crate) fn info(message: impl Display) {
+    log(Level::Info, message)
+}
+
+pub(crate) fn debug(message: impl Display) {
+    log(Level::Debug, message)
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LogFormat {
+    /// `host ident authuser [date] "request" status bytes`
+    Common,
+    /// The Common Log Format followed by the quoted `Referer` and `User-Agent`.
+    Combined,
+    /// One JSON object per line.
+    Json,
+}
+
+#[derive(Debug, Clone)]
+pub struct AccessLogEntry {
+    pub client: SocketAddr,
+    pub time: DateTime,
+    /// The method, request target and version, or `None` if the request could not be parsed.
+    pub request: Option<(Method, String, Version)>,
+    pub status: StatusCode,
+    /// The length of the body sent, or `None` if sending it failed.
+    pub bytes: Option<u64>,
+    pub duration: Duration,
+    pub referrer: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+impl AccessLogEntry {
+    pub(crate) fn started(client: SocketAddr, req: Option<&Request>) -> Self {
+        Self {
+            client,
+            time: Utc::now(),
+            request: req.map(|r| (r.method(), r.url().target(), r.version())),
+            status: StatusCode::OK,
+            bytes: None,
+            duration: Duration::default(),
+            referrer: req.and_then(|r| r.header().get_first("Referer")).map(String::from),
+            user_agent: req.and_then(|r| r.header().get_first("User-Agent")).map(String::from),
+        }
+    }
+
+    pub fn format(&self, format: LogFormat) -> String {
+        let request = self.request.as_ref()
+            .map(|(method, target, version)| format!("{} {} {}", method.as_str(), target, version.as_str()));
+
+        match format {
+            LogFormat::Common => self.common(request.as_deref()),
+            LogFormat::Combined => format!(
+                "{} \"{}\" \"{}\"",
+                self.common(request.as_deref()),
+                clf_escape(self.referrer.as_deref().unwrap_or("-")),
+                clf_escape(self.user_agent.as_deref().unwrap_or("-")),
+            ),
+            LogFormat::Json => {
+                let string = |s: Option<&str>| serde_json::to_string(&s).expect("Strings always serialize");
+                format!(
+                    "{{\"client\":{},\"time\":{},\"method\":{},\"url\":{},\"version\":{},\"status\":{},\"bytes\":{},\"duration_ms\":{:.3},\"referrer\":{},\"user_agent\":{}}}",
+                    string(Some(&self.client.ip().to_string())),
+                    string(Some(&self.time.to_rfc3339())),
+                    string(self.request.as_ref().map(|r| r.0.as_str())),
+                    string(self.request.as_ref().map(|r| r.1.as_str())),
+                    string(self.request.as_ref().map(|r| r.2.as_str())),
+                    self.status,
+                    self.bytes.map(|b| b.to_string()).unwrap_or_else(|| "null".to_string()),
+                    self.duration.as_secs_f64() * 1000.0,
+                    string(self.referrer.as_deref()),
+                    string(self.user_agent.as_deref()),
+                )
+            }
+        }
+    }
+
+    fn common(&self, request: Option<&str>) -> String {
+        format!(
+            "{} - - [{}] \"{}\" {} {}",
+            self.client.ip(),
+            self.time.format("%d/%b/%Y:%H:%M:%S %z"),
+            clf_escape(request.unwrap_or("-")),
+            self.status,
+            self.bytes.map(|b| b.to_string()).unwrap_or_else(|| "-".to_string()),
+        )
+    }
+}
+
+/// Writes a line for every request a `WebServer` answers to a sink, such as a file or stdout.
+pub struct AccessLog {
+    format: LogFormat,
+    sink: Mutex<Box<dyn Write + Send>>,
+}
+
+impl AccessLog {
+    pub fn new(format: LogFormat, sink: impl Write + Send + 'static) -> Self {
+        Self { format, sink: Mutex::new(Box::new(sink)) }
+    }
+
+    pub fn stdout(format: LogFormat) -> Self {
+        Self::new(format, std::io::stdout())
+    }
+
+    pub fn record(&self, entry: &AccessLogEntry) {
+        let line = entry.format(self.format);
+        let mut sink = self.sink.lock().unwrap();
+        if let Err(e) = writeln!(sink, "{}", line).and_then(|_| sink.flush()) {
+            warn(format!("Failed to write access log! Error: {}", e));
+        }
+    }
+}
+
+fn clf_escape(s: &str) -> String {
+    s.chars()
+        .map(|c| match c {
+            '"' | '\\' => format!("\\{}", c),
+            c if c.is_control() => format!("\\x{:02x}", c as u32),
+            c => c.to_string(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn entry() -> AccessLogEntry {
+        AccessLogEntry {
+            client: "127.0.0.1:5000".parse().unwrap(),
+            time: Utc.timestamp_opt(971186136, 0).unwrap(),
+            request: Some((Method::GET, "/apache_pb.gif".to_string(), Version::HTTP10)),
+            status: StatusCode::OK,
+            bytes: Some(2326),
+            duration: Duration::from_millis(5),
+            referrer: Some("http://www.example.com/start.html".to_string()),
+            user_agent: Some("Mozilla/4.08 \"quoted\"".to_string()),
+        }
+    }
+
+    #[test]
+    fn formats() {
+        let entry = entry();
+        assert_eq!(entry.format(LogFormat::Common), "127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] \"GET /apache_pb.gif HTTP/1.0\" 200 2326");
+        assert_eq!(
+            entry.format(LogFormat::Combined),
+            "127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] \"GET /apache_pb.gif HTTP/1.0\" 200 2326 \"http://www.example.com/start.html\" \"Mozilla/4.08 \\\"quoted\\\"\""
+        );
+        assert_eq!(
+            entry.format(LogFormat::Json),
+            "{\"client\":\"127.0.0.1\",\"time\":\"2000-10-10T13:55:36+00:00\",\"method\":\"GET\",\"url\":\"/apache_pb.gif\",\"version\":\"HTTP/1.0\",\"status\":200,\"bytes\":2326,\"duration_ms\":5.000,\"referrer\":\"http://www.example.com/start.html\",\"user_agent\":\"Mozilla/4.08 \\\"quoted\\\"\"}"
+        );
+    }
+}
@@ -26,3 +26,16 @@ pub fn extension_to_mime(extension: &str) -> &str {
         _ => "application/octet-stream",
     }
 }
+
+/// Whether responses of a media type are worth compressing. Formats which are already compressed, such as most
+/// images, audio, video and archives, are not.
+pub fn is_compressible(mime: &str) -> bool {
+    let essence = mime.split(';').next().unwrap_or("").trim().to_ascii_lowercase();
+
+    essence.starts_with("text/")
+        || essence.ends_with("+json")
+        || essence.ends_with("+xml")
+        || matches!(essence.as_str(),
+            "application/javascript" | "application/json" | "application/xml" | "application/wasm"
+            | "application/x-httpd-php" | "image/bmp")
+}
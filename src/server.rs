@@ -1,15 +1,30 @@
-use std::io::{Read, Write};
-use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener};
+use std::io::{ErrorKind, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, TcpStream};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::thread::sleep;
+use std::time::Duration;
 
+use openssl::ssl::SslStream;
+
+use crate::http::{Response, StatusCode, Stream};
+use crate::log;
 use crate::thread_pool::ThreadPool;
 
+pub use crate::thread_pool::{PoolConfig, Saturation};
+
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
 pub struct Server<H: WebService + Send + Sync + 'static> {
     socket: SocketAddr,
     handler: Arc<H>,
     certificate: Option<PathBuf>,
     key: Option<PathBuf>,
+    shutdown: ShutdownHandle,
+    grace_period: Duration,
+    pool: PoolConfig,
 }
 
 impl<H: WebService + Send + Sync + 'static> Server<H> {
@@ -19,6 +34,9 @@ impl<H: WebService + Send + Sync + 'static> Server<H> {
             handler: Arc::new(handler),
             certificate: None,
             key: None,
+            shutdown: ShutdownHandle::new(),
+            grace_period: Duration::from_secs(30),
+            pool: PoolConfig::new(),
         }
     }
 
@@ -44,63 +62,164 @@ impl<H: WebService + Send + Sync + 'static> Server<H> {
         Self { key: key.map(|x| x.as_ref().to_path_buf()), ..self }
     }
 
-    pub fn run(&mut self) {
-        let mut threads = ThreadPool::new();
-        let listener = TcpListener::bind(self.socket).unwrap();
-        println!("Listening on {}:{}", self.socket.ip(), self.socket.port());
+    /// Sets the sizing of the worker pool and what happens to new connections when every worker is busy.
+    pub fn with_pool(self, pool: PoolConfig) -> Self {
+        Self { pool, ..self }
+    }
 
-        loop {
-            let handler = self.handler.clone();
-            match listener.accept() {
-                Ok((con, addr)) => {
-                    threads
-                        .submit(move || handler.handle_connection(con, addr))
-                        .unwrap();
-                }
+    /// How long `run` waits for in-flight connections to finish after shutdown is requested.
+    pub fn with_grace_period(self, grace_period: Duration) -> Self {
+        Self { grace_period, ..self }
+    }
 
-                Err(e) => {
-                    panic!("{}", e);
-                }
-            }
-        }
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
     }
 
-    pub fn run_secure(&mut self) {
-        let mut threads = ThreadPool::new();
-        let listener = TcpListener::bind(self.socket).unwrap();
-        println!("Listening on {}:{}", self.socket.ip(), self.socket.port());
+    pub fn run(&mut self) -> Result<(), Error> {
+        self.serve(Some)
+    }
 
+    pub fn run_secure(&mut self) -> Result<(), Error> {
         let tls = {
-            let mut tls = openssl::ssl::SslAcceptor::mozilla_intermediate_v5(openssl::ssl::SslMethod::tls()).unwrap();
+            let mut tls = openssl::ssl::SslAcceptor::mozilla_intermediate_v5(openssl::ssl::SslMethod::tls())
+                .map_err(Error::TLSError)?;
             tls.set_certificate_file(self.certificate.as_ref().unwrap_or(&PathBuf::from("cert.pem")), openssl::ssl::SslFiletype::PEM)
-                .expect("Expected certificate file \"cert.pem\" in working directory!");
+                .map_err(Error::TLSError)?;
             tls.set_private_key_file(self.key.as_ref().unwrap_or(&PathBuf::from("key.pem")), openssl::ssl::SslFiletype::PEM)
-                .expect("Expected key file \"key.pem\" in working directory!");
+                .map_err(Error::TLSError)?;
             tls.build()
         };
 
-        loop {
+        self.serve(move |con| tls.accept(con).ok())
+    }
+
+    fn serve<C, F>(&mut self, wrap: F) -> Result<(), Error>
+        where C: Connection + Send + 'static, F: Fn(TcpStream) -> Option<C> + Send + Sync + 'static
+    {
+        let wrap = Arc::new(wrap);
+        let mut threads = ThreadPool::new(self.pool);
+        let listener = TcpListener::bind(self.socket).map_err(Error::IOError)?;
+        listener.set_nonblocking(true).map_err(Error::IOError)?;
+        log::info(format!("Listening on {}:{}", self.socket.ip(), self.socket.port()));
+
+        while !self.shutdown.is_shutdown() {
             let handler = self.handler.clone();
             match listener.accept() {
                 Ok((con, addr)) => {
-                    let con = match tls.accept(con) {
-                        Ok(c) => c,
-                        Err(_) => continue,
-                    };
-
-                    threads
-                        .submit(move || handler.handle_connection(con, addr))
-                        .unwrap();
+                    if let Err(e) = con.set_nonblocking(false) {
+                        log::warn(format!("Failed to configure connection from {}! Error: {}", addr, e));
+                        continue;
+                    }
+
+                    // Wrapping may wait on the client, such as for a TLS handshake, so it is done by the worker.
+                    if threads.admit() {
+                        let wrap = wrap.clone();
+                        threads.submit(move || {
+                            if let Some(con) = handshake(&*wrap, con) {
+                                handler.handle_connection(con, addr);
+                            }
+                        });
+                    } else if let Some(con) = handshake(&*wrap, con) {
+                        handler.reject_connection(con, addr);
+                    }
                 }
 
+                Err(e) if e.kind() == ErrorKind::WouldBlock => sleep(ACCEPT_POLL_INTERVAL),
+
+                // Errors such as running out of file descriptors or a client resetting before being accepted are
+                // usually transient, so back off and keep serving rather than taking the whole server down.
                 Err(e) => {
-                    panic!("{}", e);
+                    log::error(format!("Failed to accept connection! Error: {}", e));
+                    sleep(ACCEPT_POLL_INTERVAL);
                 }
             }
         }
+
+        log::info(format!("Shutting down, waiting up to {:?} for open connections", self.grace_period));
+        if !threads.shutdown(self.grace_period) {
+            log::warn("Grace period expired with connections still open");
+        }
+
+        Ok(())
+    }
+}
+
+fn handshake<C: Connection>(wrap: impl Fn(TcpStream) -> Option<C>, con: TcpStream) -> Option<C> {
+    con.set_read_timeout(Some(HANDSHAKE_TIMEOUT)).ok()?;
+    con.set_write_timeout(Some(HANDSHAKE_TIMEOUT)).ok()?;
+    let con = wrap(con)?;
+    con.set_read_timeout(None).ok()?;
+    con.set_write_timeout(None).ok()?;
+    Some(con)
+}
+
+/// A cloneable handle used to ask a running `Server` to stop accepting connections and return.
+#[derive(Debug, Clone)]
+pub struct ShutdownHandle {
+    requested: Arc<AtomicBool>,
+}
+
+impl ShutdownHandle {
+    fn new() -> Self {
+        Self { requested: Arc::new(AtomicBool::new(false)) }
+    }
+
+    pub fn shutdown(&self) {
+        self.requested.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_shutdown(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    IOError(std::io::Error),
+    TLSError(openssl::error::ErrorStack),
+}
+
+/// A stream a `WebService` is served over, whose timeouts can be adjusted while it is being served.
+/// The default methods ignore timeouts, for streams which cannot block such as in-memory buffers.
+pub trait Connection: Read + Write {
+    fn set_read_timeout(&self, _timeout: Option<Duration>) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    fn set_write_timeout(&self, _timeout: Option<Duration>) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Connection for TcpStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        TcpStream::set_write_timeout(self, timeout)
+    }
+}
+
+impl<S: Connection> Connection for SslStream<S> {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        self.get_ref().set_read_timeout(timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        self.get_ref().set_write_timeout(timeout)
     }
 }
 
 pub trait WebService {
-    fn handle_connection(&self, con: impl Read + Write, client: SocketAddr);
+    fn handle_connection(&self, con: impl Connection, client: SocketAddr);
+
+    /// Called on the accepting thread instead of `handle_connection` when the worker pool is saturated.
+    fn reject_connection(&self, con: impl Connection, _client: SocketAddr) {
+        let response = Response::new(StatusCode::SERVICE_UNAVAILABLE)
+            .with_header("Retry-After", "1")
+            .with_header("Connection", "close");
+        let _ = Stream::new(con).send(response);
+    }
 }
@@ -1,124 +1,298 @@
-use std::sync::{
-    atomic::{AtomicBool, Ordering as AtomicOrd},
-    mpsc::{self, SendError},
-    Arc,
-};
-use std::thread::{spawn, JoinHandle};
+use std::collections::VecDeque;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::spawn;
+use std::time::{Duration, Instant};
 
-pub struct ThreadPool {
-    capacity: Option<usize>,
-    workers: Vec<Worker>,
+use crate::log;
+
+/// What a `ThreadPool` does with new jobs once every worker is busy.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Saturation {
+    /// Hold up to this many jobs in the queue until a worker frees up, rejecting any beyond that.
+    Queue(usize),
+    Reject,
+    /// Block the submitting thread until a worker is free.
+    Block,
 }
 
-impl ThreadPool {
+#[derive(Debug, Copy, Clone)]
+pub struct PoolConfig {
+    min_workers: usize,
+    max_workers: usize,
+    idle_timeout: Option<Duration>,
+    saturation: Saturation,
+}
+
+impl PoolConfig {
     pub fn new() -> Self {
-        ThreadPool {
-            capacity: None,
-            workers: Vec::new(),
+        Self {
+            min_workers: 0,
+            max_workers: 64,
+            idle_timeout: Some(Duration::from_secs(60)),
+            saturation: Saturation::Queue(256),
         }
     }
 
-    pub fn submit(&mut self, job: impl FnOnce() + Sync + Send + 'static) -> Result<(), ()> {
-        let free_worker = self
-            .workers
-            .iter_mut()
-            .find(|w| !w.busy.load(AtomicOrd::Relaxed));
+    /// The number of workers kept alive even when idle.
+    pub fn with_min_workers(self, min_workers: usize) -> Self {
+        Self { min_workers, ..self }
+    }
+
+    pub fn with_max_workers(self, max_workers: usize) -> Self {
+        Self { max_workers: max_workers.max(1), ..self }
+    }
+
+    /// How long a worker above the minimum may sit idle before it exits, or `None` to keep workers forever.
+    pub fn with_idle_timeout(self, idle_timeout: Option<Duration>) -> Self {
+        Self { idle_timeout, ..self }
+    }
+
+    pub fn with_saturation(self, saturation: Saturation) -> Self {
+        Self { saturation, ..self }
+    }
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+
+pub struct ThreadPool {
+    config: PoolConfig,
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    work: Condvar,
+    progress: Condvar,
+}
+
+struct State {
+    jobs: VecDeque<Job>,
+    workers: usize,
+    idle: usize,
+    terminate: bool,
+}
+
+impl ThreadPool {
+    pub fn new(config: PoolConfig) -> Self {
+        let pool = ThreadPool {
+            config,
+            shared: Arc::new(Shared {
+                state: Mutex::new(State { jobs: VecDeque::new(), workers: 0, idle: 0, terminate: false }),
+                work: Condvar::new(),
+                progress: Condvar::new(),
+            }),
+        };
 
-        match free_worker {
-            Some(w) => {
-                w.send(Message::NewJob(Box::new(job))).map_err(|_| ())?;
+        {
+            let mut state = pool.lock();
+            for _ in 0..config.min_workers.min(config.max_workers) {
+                pool.spawn_worker(&mut state);
             }
+        }
 
-            None => match self.capacity {
-                Some(c) if self.workers.len() < c => {
-                    let mut w = Worker::new();
-                    w.send(Message::NewJob(Box::new(job))).map_err(|_| ())?;
-                    self.workers.push(w);
-                }
+        pool
+    }
+
+    /// Whether another job can be submitted right now without exceeding the configured limits.
+    /// With `Saturation::Block` this waits until a worker is free and always returns true.
+    pub fn admit(&self) -> bool {
+        let mut state = self.lock();
 
-                Some(_) => {
-                    use rand::random;
-                    let choice = random::<usize>() % self.workers.len();
-                    self.workers[choice]
-                        .send(Message::NewJob(Box::new(job)))
-                        .map_err(|_| ())?;
+        match self.config.saturation {
+            Saturation::Queue(limit) => self.free_slots(&state) + limit > state.jobs.len(),
+            Saturation::Reject => self.free_slots(&state) > state.jobs.len(),
+            Saturation::Block => {
+                while self.free_slots(&state) <= state.jobs.len() {
+                    state = self.shared.progress.wait(state).unwrap();
                 }
+                true
+            }
+        }
+    }
+
+    /// Queues a job to be run by the next free worker, starting a new worker if none are idle.
+    pub fn submit(&self, job: impl FnOnce() + Send + 'static) {
+        let mut state = self.lock();
+        state.jobs.push_back(Box::new(job));
+
+        if state.idle < state.jobs.len() && state.workers < self.config.max_workers {
+            self.spawn_worker(&mut state);
+        }
+
+        self.shared.work.notify_one();
+    }
+
+    /// Stops every worker once the queue is drained, waiting at most `grace` for them to finish.
+    /// Returns false if some workers were still busy when the grace period ran out; those are left to finish
+    /// in the background.
+    pub fn shutdown(&mut self, grace: Duration) -> bool {
+        self.terminate(Some(Instant::now() + grace))
+    }
 
-                None => {
-                    let mut w = Worker::new();
-                    w.send(Message::NewJob(Box::new(job))).map_err(|_| ())?;
-                    self.workers.push(w);
+    fn terminate(&mut self, deadline: Option<Instant>) -> bool {
+        let mut state = self.lock();
+        state.terminate = true;
+        self.shared.work.notify_all();
+
+        while state.workers > 0 {
+            state = match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return false;
+                    }
+                    self.shared.progress.wait_timeout(state, deadline - now).unwrap().0
                 }
-            },
+                None => self.shared.progress.wait(state).unwrap(),
+            };
         }
 
-        Ok(())
+        true
+    }
+
+    fn free_slots(&self, state: &State) -> usize {
+        state.idle + self.config.max_workers.saturating_sub(state.workers)
+    }
+
+    fn spawn_worker(&self, state: &mut State) {
+        state.workers += 1;
+        state.idle += 1;
+
+        let shared = self.shared.clone();
+        let config = self.config;
+        spawn(move || worker(shared, config));
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.shared.state.lock().unwrap()
     }
 }
 
 impl Drop for ThreadPool {
     fn drop(&mut self) {
-        println!("Shutting Down Thread Pool!");
-        for worker in &mut self.workers {
-            worker.send(Message::Terminate).unwrap();
-            worker.join().unwrap();
+        // After `shutdown` the workers have already been told to stop, and any still busy once its grace period
+        // ran out are left to finish in the background rather than waited on here.
+        if !self.lock().terminate {
+            self.terminate(None);
         }
     }
 }
 
-struct Worker {
-    busy: Arc<AtomicBool>,
-    handle: Option<JoinHandle<()>>,
-    sender: mpsc::Sender<Message>,
-}
+fn worker(shared: Arc<Shared>, config: PoolConfig) {
+    loop {
+        let job = {
+            let mut state = shared.state.lock().unwrap();
 
-impl Worker {
-    fn new() -> Self {
-        let (sender, receiver) = mpsc::channel();
-        let busy = Arc::new(AtomicBool::new(false));
+            loop {
+                if let Some(job) = state.jobs.pop_front() {
+                    state.idle -= 1;
+                    break job;
+                }
+
+                if state.terminate {
+                    return exit(&shared, &mut state);
+                }
 
-        let mut worker = Worker {
-            busy: busy.clone(),
-            handle: None,
-            sender,
+                match config.idle_timeout {
+                    Some(timeout) => {
+                        let (s, wait) = shared.work.wait_timeout(state, timeout).unwrap();
+                        state = s;
+                        if wait.timed_out() && state.jobs.is_empty() && state.workers > config.min_workers {
+                            return exit(&shared, &mut state);
+                        }
+                    }
+                    None => state = shared.work.wait(state).unwrap(),
+                }
+            }
         };
 
-        worker.handle = Some(spawn(|| Worker::handler(receiver, busy)));
+        shared.progress.notify_all();
 
-        return worker;
-    }
+        // A panicking job must not take the worker, and the pool's bookkeeping, down with it.
+        if catch_unwind(AssertUnwindSafe(job)).is_err() {
+            log::error("A job panicked in the thread pool");
+        }
 
-    fn send(&mut self, msg: Message) -> Result<(), SendError<Message>> {
-        self.sender.send(msg)?;
-        return Ok(());
+        shared.state.lock().unwrap().idle += 1;
+        shared.progress.notify_all();
     }
+}
 
-    fn join(&mut self) -> Result<(), ()> {
-        match self.handle.take() {
-            Some(j) => {
-                j.join().map_err(|_| ())?;
-                Ok(())
-            }
+fn exit(shared: &Shared, state: &mut State) {
+    state.workers -= 1;
+    state.idle -= 1;
+    shared.progress.notify_all();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::mpsc::channel;
+
+    #[test]
+    fn runs_queued_jobs_on_bounded_workers() {
+        let mut pool = ThreadPool::new(PoolConfig::new().with_max_workers(2).with_saturation(Saturation::Queue(8)));
+        let count = Arc::new(AtomicUsize::new(0));
 
-            None => Ok(()),
+        for _ in 0..8 {
+            assert!(pool.admit());
+            let count = count.clone();
+            pool.submit(move || {
+                count.fetch_add(1, Ordering::SeqCst);
+            });
         }
+
+        assert!(pool.lock().workers <= 2);
+        assert!(pool.shutdown(Duration::from_secs(5)));
+        assert_eq!(count.load(Ordering::SeqCst), 8);
     }
 
-    fn handler(receiver: mpsc::Receiver<Message>, busy: Arc<AtomicBool>) {
-        loop {
-            busy.store(false, AtomicOrd::Relaxed);
-            match receiver.recv().unwrap() {
-                Message::Terminate => return,
-                Message::NewJob(j) => {
-                    busy.store(true, AtomicOrd::Relaxed);
-                    j();
-                }
-            }
-        }
+    #[test]
+    fn rejects_when_saturated() {
+        let mut pool = ThreadPool::new(PoolConfig::new().with_max_workers(1).with_saturation(Saturation::Reject));
+        let (sender, receiver) = channel::<()>();
+
+        assert!(pool.admit());
+        pool.submit(move || {
+            let _ = receiver.recv();
+        });
+        assert!(!pool.admit());
+
+        drop(sender);
+        assert!(pool.shutdown(Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn detaches_workers_after_grace_period() {
+        let mut pool = ThreadPool::new(PoolConfig::new());
+        pool.submit(|| std::thread::sleep(Duration::from_secs(5)));
+
+        let started = Instant::now();
+        assert!(!pool.shutdown(Duration::from_millis(100)));
+        drop(pool);
+        assert!(started.elapsed() < Duration::from_secs(1));
     }
-}
 
-enum Message {
-    Terminate,
-    NewJob(Box<dyn FnOnce() + Send + Sync>),
+    #[test]
+    fn reaps_idle_workers() {
+        let pool = ThreadPool::new(PoolConfig::new()
+            .with_min_workers(1)
+            .with_max_workers(4)
+            .with_idle_timeout(Some(Duration::from_millis(50))));
+
+        for _ in 0..4 {
+            pool.submit(|| std::thread::sleep(Duration::from_millis(20)));
+        }
+
+        std::thread::sleep(Duration::from_millis(300));
+        assert_eq!(pool.lock().workers, 1);
+    }
 }
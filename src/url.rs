@@ -20,7 +20,7 @@ impl URL {
             password: None,
             host: None,
             port: None,
-            resource: vec!["/".to_string()],
+            resource: Vec::new(),
             parameters: HashMap::new(),
         }
     }
@@ -31,10 +31,12 @@ impl URL {
         }
 
         let s = s.to_string();
+        let decode = |s: &str| decode(s).map_err(|_| ());
 
         let (protocol, s) = s.split_once("://").unwrap_or(("", &s));
         let (user, s) = s.split_once("@").unwrap_or(("", s));
-        let (host, resource) = s.split_once("/").ok_or(())?;
+        // A URL with only an authority, such as `http://example.com`, is for the root.
+        let (host, resource) = s.split_at(s.find(['/', '?']).unwrap_or(s.len()));
         let (host, port) = host.split_once(":").unwrap_or((host, ""));
         let (username, password) = user.split_once(":").unwrap_or(("", ""));
 
@@ -43,36 +45,36 @@ impl URL {
         let parameters: HashMap<String, String> = parameters.split("&")
             .filter(|s| !s.is_empty())
             .map(|p| p.split_once("=").unwrap_or((p, "")))
-            .map(|(a, b)| (decode(a), decode(b)))
-            .collect();
+            .map(|(a, b)| Ok((decode(a)?, decode(b)?)))
+            .collect::<Result<_, ()>>()?;
 
         Ok(Self {
             protocol: if protocol.is_empty() {
                 None
             } else {
-                Some(decode(protocol.to_string()))
+                Some(decode(protocol)?)
             },
             username: if username.is_empty() {
                 None
             } else {
-                Some(decode(user.to_string()))
+                Some(decode(username)?)
             },
             password: if password.is_empty() {
                 None
             } else {
-                Some(decode(password.to_string()))
+                Some(decode(password)?)
             },
             host: if host.is_empty() {
                 None
             } else {
-                Some(decode(host.to_string()))
+                Some(decode(host)?)
             },
             port: if port.is_empty() {
                 None
             } else {
                 Some(port.parse().map_err(|_| ())?)
             },
-            resource: resource.split("/").filter(|s| !s.is_empty()).map(|s| decode(s)).collect(),
+            resource: resource.split("/").filter(|s| !s.is_empty()).map(decode).collect::<Result<_, ()>>()?,
             parameters,
         })
     }
@@ -158,11 +160,38 @@ impl URL {
         self.parameters.get(key.borrow()).map(|s| s.as_str())
     }
 
+    pub fn params(&self) -> &HashMap<String, String> {
+        &self.parameters
+    }
+
     pub fn with_param(mut self, key: impl Borrow<str>, value: impl Borrow<str>) -> Self {
         self.parameters.insert(key.borrow().borrow().to_string(), value.borrow().to_string());
         self
     }
 
+    /// The encoded path and query, as used in the request line sent to a server.
+    pub fn target(&self) -> String {
+        let mut s: String = self.resource.iter().map(|s| format!("/{}", encode(s))).collect();
+
+        if s.is_empty() {
+            s += "/";
+        }
+
+        if !self.parameters.is_empty() {
+            s += "?";
+            s += &self.query();
+        }
+
+        s
+    }
+
+    // Parameters are sorted so the same URL is always written the same way.
+    fn query(&self) -> String {
+        let mut parameters: Vec<_> = self.parameters.iter().collect();
+        parameters.sort();
+        parameters.iter().map(|(key, value)| format!("{}={}", encode(*key), encode(*value))).collect::<Vec<_>>().join("&")
+    }
+
     pub fn as_string(&self) -> Result<String, ()> {
         let mut s = String::new();
 
@@ -186,7 +215,7 @@ impl URL {
 
         if !self.parameters.is_empty() {
             s += "?";
-            s += &self.parameters.iter().map(|(key, value)| format!("{}={}", encode(key), encode(value))).reduce(|a, b| format!("{}&{}", a, b)).unwrap();
+            s += &self.query();
         }
 
         if s.is_ascii() {
@@ -197,37 +226,50 @@ impl URL {
     }
 }
 
-pub fn decode(s: impl Borrow<str>) -> String {
-    let mut s = s.borrow().to_owned();
-
-    while let Some(i) = s.find("%") {
-        let (prefix, middle, suffix) = (
-            (0..i)
-                .map(|n| s.chars().nth(n).unwrap())
-                .collect::<String>(),
-            (i + 1..i + 3)
-                .map(|n| s.chars().nth(n).unwrap())
-                .collect::<String>(),
-            (i + 3..s.len())
-                .map(|n| s.chars().nth(n).unwrap())
-                .collect::<String>(),
-        );
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    /// A `%` which is not followed by two hexadecimal digits.
+    InvalidEscape,
+    InvalidUtf8,
+}
 
-        let decoded = u8::from_str_radix(&middle, 16).map_err(|_| ()).unwrap() as char;
-        s = format!("{}{}{}", prefix, decoded, suffix);
+/// Decodes percent-encoded octets, failing on malformed escapes or if the result is not valid UTF-8.
+pub fn decode(s: impl Borrow<str>) -> Result<String, DecodeError> {
+    let bytes = s.borrow().as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let hex = bytes.get(i + 1..i + 3)
+                    .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+                    .and_then(|hex| std::str::from_utf8(hex).ok())
+                    .ok_or(DecodeError::InvalidEscape)?;
+
+                decoded.push(u8::from_str_radix(hex, 16).map_err(|_| DecodeError::InvalidEscape)?);
+                i += 3;
+            }
+
+            b => {
+                decoded.push(b);
+                i += 1;
+            }
+        }
     }
 
-    return s;
+    String::from_utf8(decoded).map_err(|_| DecodeError::InvalidUtf8)
 }
 
+/// Percent-encodes every octet of the UTF-8 encoding of `s` except unreserved characters.
 pub fn encode<S: Borrow<str>>(s: &S) -> String {
     s.borrow()
-        .chars()
-        .map(|c| match c {
-            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' | '~' | '.' => format! {"{}", c},
-            _ => format!("%{:02X?}", (c as u8)),
+        .bytes()
+        .map(|b| match b {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'_' | b'~' | b'.' => format! {"{}", b as char},
+            _ => format!("%{:02X}", b),
         })
-        .fold(String::new(), |a, b| format!("{}{}", a, b))
+        .collect()
 }
 
 #[cfg(test)]
@@ -245,6 +287,33 @@ mod test {
         println!("{}", url.as_string().unwrap());
     }
 
+    #[test]
+    fn malformed_escapes() {
+        assert_eq!(decode("a%20b"), Ok("a b".to_string()));
+        assert_eq!(decode("%E2%9C%93"), Ok("\u{2713}".to_string()));
+        assert!(decode("100%").is_err());
+        assert!(decode("%zz").is_err());
+        assert_eq!(decode("%+f"), Err(DecodeError::InvalidEscape));
+        assert_eq!(decode("%C3%28"), Err(DecodeError::InvalidUtf8));
+        assert_eq!(decode(encode(&"\u{2713} a=b")), Ok("\u{2713} a=b".to_string()));
+        assert!(URL::from_string("/bad%2").is_err());
+    }
+
+    #[test]
+    fn origin_only() {
+        let url = URL::from_string("http://example.com").unwrap();
+        assert_eq!(url.host().map(String::as_str), Some("example.com"));
+        assert_eq!(url.target(), "/");
+
+        let url = URL::from_string("http://example.com:8080?q=a%20b").unwrap();
+        assert_eq!(url.port(), Some(&8080));
+        assert_eq!(url.param("q"), Some("a b"));
+        assert_eq!(url.target(), "/?q=a%20b");
+
+        let url = URL::from_string("/search?z=1&b=2&q=3&a=4").unwrap();
+        assert_eq!(url.target(), "/search?a=4&b=2&q=3&z=1");
+    }
+
     #[test]
     fn url_decoding() {
         let url = URL::from_string("https://justnoise.net:25565/el%20diablo/the%20devil.png?qualude=a_mile").unwrap();
@@ -1,16 +1,18 @@
-use std::io::{Read, Write};
-use crate::http::{Response, Stream as HTTPStream};
+use std::io::{Cursor, Read, Write};
+use crate::http::{Response, StatusCode, Stream as HTTPStream};
 use crate::ws::{Error, Message};
 use crate::ws::frame::DataFrame;
+use crate::server::Connection;
 
 pub struct Stream<S>
 where
     S: Read + Write,
 {
     connection: S,
+    buffered: Cursor<Vec<u8>>,
 }
 
-impl<S: Read + Write> Stream<S> {
+impl<S: Connection> Stream<S> {
     pub fn await_handshake(mut http: HTTPStream<S>) -> Result<Self, Error> {
         loop {
             let request = http.recv().map_err(|e| Error::HTTPError(e))?;
@@ -36,21 +38,25 @@ impl<S: Read + Write> Stream<S> {
                 )[..],
             );
 
-            let response = Response::new(101)
+            let response = Response::new(StatusCode::SWITCHING_PROTOCOLS)
                 .with_header("Upgrade", "websocket")
                 .with_header("Connection", "Upgrade")
                 .with_header("Sec-WebSocket-Accept", accept_key.as_str());
 
             http.send(response).map_err(|e| Error::HTTPError(e))?;
 
+            let (connection, buffered) = http.into_inner();
             return Ok(Self {
-                connection: http.into_inner(),
+                connection,
+                buffered: Cursor::new(buffered),
             });
         }
     }
+}
 
+impl<S: Read + Write> Stream<S> {
     pub fn recv(&mut self) -> Result<Message, Error> {
-        DataFrame::read_from(&mut self.connection).map(|f| f.into())
+        DataFrame::read_from(&mut (&mut self.buffered).chain(&mut self.connection)).map(|f| f.into())
     }
 
     pub fn send(&mut self, message: Message) -> Result<(), Error> {